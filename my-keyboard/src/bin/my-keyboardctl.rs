@@ -0,0 +1,118 @@
+//! Client for the control socket of a running my-keyboard.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+};
+
+use anyhow::{bail, Context, Error};
+use clap::{Parser, Subcommand};
+use protocol::{Request, Response};
+
+#[path = "../protocol.rs"]
+mod protocol;
+
+/// Control a running my-keyboard.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+    /// Control socket [default: $XDG_RUNTIME_DIR/my-keyboard/control.sock]
+    #[arg(long)]
+    socket: Option<PathBuf>,
+    /// Print the response as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Switch to a random effect
+    Next,
+    /// Go back to the previous effect
+    Previous,
+    /// Switch to an effect, e.g. "effect_ripple"
+    Set { effect: String },
+    /// Pause effects
+    Pause,
+    /// Resume effects
+    Resume,
+    /// Set the brightness, from 0 (Off) to 1
+    Brightness { brightness: f32 },
+    /// Load the config file again
+    Reload,
+    /// Show the current effect, every effect & the state
+    Status,
+    /// Color a group over everything for some seconds, e.g. `overlay wasd "#FF0000"`
+    Overlay {
+        group: String,
+        /// Hex color, e.g. "#FF0000"
+        color: String,
+        #[arg(long, default_value_t = 5.0)]
+        seconds: f32,
+    },
+    /// Show frame timing metrics
+    Metrics,
+}
+
+impl From<Command> for Request {
+    fn from(command: Command) -> Self {
+        match command {
+            Command::Next => Request::NextEffect,
+            Command::Previous => Request::PreviousEffect,
+            Command::Set { effect } => Request::SetEffect { effect },
+            Command::Pause => Request::Pause,
+            Command::Resume => Request::Resume,
+            Command::Brightness { brightness } => Request::SetBrightness { brightness },
+            Command::Reload => Request::ReloadConfig,
+            Command::Status => Request::Status,
+            Command::Overlay {
+                group,
+                color,
+                seconds,
+            } => Request::Overlay {
+                group,
+                color,
+                seconds,
+            },
+            Command::Metrics => Request::Metrics,
+        }
+    }
+}
+
+fn main() -> Result<(), Error> {
+    let args = Args::parse();
+    let path = args.socket.unwrap_or_else(protocol::default_socket_path);
+
+    let mut stream = UnixStream::connect(&path)
+        .with_context(|| format!("Failed to connect to {path:?}, is my-keyboard running?"))?;
+    let mut request = serde_json::to_string(&Request::from(args.command))?;
+    request.push('\n');
+    stream.write_all(request.as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    if args.json {
+        print!("{line}");
+        return Ok(());
+    }
+    let response: Response =
+        serde_json::from_str(&line).context("Invalid response from my-keyboard")?;
+    match response {
+        Response::Ok => {}
+        Response::Status(status) => {
+            println!("Effect: {}", status.effect.as_deref().unwrap_or("None"));
+            println!("Paused: {}", status.paused);
+            println!("Brightness: {}", status.brightness);
+            println!("Locked: {}", status.locked);
+            println!("Idle: {}", status.idle);
+            println!("Effects: {}", status.effects.join(", "));
+        }
+        Response::Metrics { summary } => println!("{summary}"),
+        Response::Frame { .. } => bail!("Unexpected frame from my-keyboard"),
+        Response::Error { message } => bail!(message),
+    }
+    Ok(())
+}
@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+use crate::render::ScriptedPress;
+
+/// Custom lighting effects for Razer keyboards.
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Config file [default: $XDG_CONFIG_HOME/my-keyboard/config.toml]
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Layout file, instead of the built in layout for the keyboard
+    #[arg(long, global = true)]
+    pub layout: Option<PathBuf>,
+    /// Layout variant, e.g. "iso"
+    #[arg(long, global = true)]
+    pub layout_variant: Option<String>,
+    /// Seed for random effects, to replay a run [default: Random]
+    #[arg(long, global = true)]
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Default, Subcommand)]
+pub enum Command {
+    /// Run the lighting effects (Default)
+    #[default]
+    Run,
+    /// Render an effect without a keyboard, to a PNG sequence or an animated GIF
+    Render(RenderArgs),
+    /// Simulate the keyboard in the terminal, typing in it feeds the effects
+    Simulate {
+        /// Show the frames of the running my-keyboard instead
+        #[arg(long)]
+        attach: bool,
+        /// Control socket to attach to [default: control_socket from the config]
+        #[arg(long, requires = "attach")]
+        socket: Option<PathBuf>,
+    },
+    /// Build a layout file by lighting up each matrix cell & pressing the lit key
+    MapKeys {
+        /// Layout file to write (.toml or .json)
+        output: PathBuf,
+        /// Name of the layout [default: Name of the keyboard]
+        #[arg(long)]
+        name: Option<String>,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+pub struct RenderArgs {
+    /// Effect identifier, e.g. "effect_ripple"
+    pub effect: String,
+    /// GIF file, or a directory for a PNG sequence
+    pub output: PathBuf,
+    /// Seconds to render
+    #[arg(long, default_value_t = 5.0, value_parser = parse_positive)]
+    pub seconds: f32,
+    /// Frames per second, up to 100 as GIF frame delays are in hundredths of a second
+    #[arg(long, default_value_t = 15, value_parser = clap::value_parser!(u32).range(1..=100))]
+    pub fps: u32,
+    /// Pixels per millimetre
+    #[arg(long, default_value_t = 2.0, value_parser = parse_positive)]
+    pub scale: f32,
+    /// Key press as SECONDS:KEY[:HOLD_SECONDS], e.g. "0.5:KEY_A" (Repeatable)
+    #[arg(long = "press")]
+    pub presses: Vec<ScriptedPress>,
+}
+
+fn parse_positive(str: &str) -> Result<f32, String> {
+    let value = str.parse::<f32>().map_err(|err| err.to_string())?;
+    if value <= 0.0 || !value.is_finite() {
+        return Err("must be a number above 0".to_owned());
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Parser;
+
+    use super::{Args, Command, RenderArgs};
+
+    fn render(arg: &str, value: &str) -> Result<RenderArgs, clap::Error> {
+        let args = Args::try_parse_from([
+            "my-keyboard",
+            "render",
+            "effect_ripple",
+            "out.gif",
+            arg,
+            value,
+        ])?;
+        match args.command {
+            Some(Command::Render(args)) => Ok(args),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn render_scale() {
+        assert_eq!(render("--scale", "0.5").unwrap().scale, 0.5);
+        for scale in ["0", "-1", "inf", "NaN", "big"] {
+            assert!(render("--scale", scale).is_err(), "{scale}");
+        }
+    }
+
+    #[test]
+    fn render_length() {
+        assert_eq!(render("--seconds", "0.5").unwrap().seconds, 0.5);
+        for seconds in ["0", "-1", "inf", "NaN"] {
+            assert!(render("--seconds", seconds).is_err(), "{seconds}");
+        }
+        assert_eq!(render("--fps", "100").unwrap().fps, 100);
+        for fps in ["0", "101"] {
+            assert!(render("--fps", fps).is_err(), "{fps}");
+        }
+    }
+}
@@ -0,0 +1,135 @@
+//! Frame clock, so effects can be paused, sped up & tested without real time.
+
+use std::time::{Duration, Instant};
+
+/// Time of the frame being drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameTime {
+    /// Scaled time since the clock started, doesn't advance while paused.
+    pub elapsed: Duration,
+    /// Scaled time since the last frame.
+    pub dt: Duration,
+    /// Number of the frame, starting at 0.
+    pub frame: u64,
+}
+
+impl FrameTime {
+    pub fn dt_secs(&self) -> f32 {
+        self.dt.as_secs_f32()
+    }
+
+    /// Time since `start`, which is set to now if it is [`None`].
+    pub fn since(&self, start: &mut Option<Duration>) -> Duration {
+        self.elapsed
+            .saturating_sub(*start.get_or_insert(self.elapsed))
+    }
+}
+
+#[derive(Debug)]
+pub struct FrameClock {
+    time: Option<FrameTime>,
+    last_tick: Option<Instant>,
+    paused: bool,
+    time_scale: f32,
+}
+
+impl Default for FrameClock {
+    fn default() -> Self {
+        Self {
+            time: None,
+            last_tick: None,
+            paused: false,
+            time_scale: 1.0,
+        }
+    }
+}
+
+impl FrameClock {
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// How fast effects run, 1.0 is real time.
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.time_scale = time_scale.max(0.0);
+    }
+
+    /// Advance by the real time since the last tick.
+    pub fn tick(&mut self, now: Instant) -> FrameTime {
+        let real_dt = self
+            .last_tick
+            .map(|last_tick| now.saturating_duration_since(last_tick))
+            .unwrap_or_default();
+        self.last_tick = Some(now);
+        self.step(real_dt)
+    }
+
+    /// Advance by `real_dt` by hand, scaled by the time scale & nothing if paused.
+    pub fn step(&mut self, real_dt: Duration) -> FrameTime {
+        let dt = if self.paused {
+            Duration::ZERO
+        } else {
+            // Time scales too large for a duration run as fast as possible.
+            Duration::try_from_secs_f64(real_dt.as_secs_f64() * self.time_scale as f64)
+                .unwrap_or(Duration::MAX)
+        };
+        let time = match self.time {
+            Some(time) => FrameTime {
+                elapsed: time.elapsed.saturating_add(dt),
+                dt,
+                frame: time.frame + 1,
+            },
+            None => FrameTime::default(),
+        };
+        self.time = Some(time);
+        time
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::FrameClock;
+
+    #[test]
+    fn step() {
+        let mut clock = FrameClock::default();
+        let time = clock.step(Duration::from_millis(100));
+        assert_eq!((time.elapsed, time.frame), (Duration::ZERO, 0));
+
+        let time = clock.step(Duration::from_millis(100));
+        assert_eq!(time.elapsed, Duration::from_millis(100));
+        assert_eq!(time.frame, 1);
+
+        clock.set_time_scale(0.5);
+        let time = clock.step(Duration::from_millis(200));
+        assert_eq!(time.elapsed, Duration::from_millis(200));
+        assert_eq!(time.dt, Duration::from_millis(100));
+
+        clock.set_paused(true);
+        let time = clock.step(Duration::from_millis(100));
+        assert_eq!(time.elapsed, Duration::from_millis(200));
+        assert_eq!(time.dt, Duration::ZERO);
+        assert_eq!(time.frame, 3);
+
+        let mut start = None;
+        assert_eq!(time.since(&mut start), Duration::ZERO);
+        clock.set_paused(false);
+        clock.set_time_scale(1.0);
+        assert_eq!(
+            clock.step(Duration::from_secs(1)).since(&mut start),
+            Duration::from_secs(1)
+        );
+
+        clock.set_time_scale(f32::INFINITY);
+        let time = clock.step(Duration::from_millis(16));
+        assert_eq!((time.elapsed, time.dt), (Duration::MAX, Duration::MAX));
+        clock.set_time_scale(1e30);
+        assert_eq!(clock.step(Duration::ZERO).dt, Duration::ZERO);
+    }
+}
@@ -0,0 +1,262 @@
+//! Stacks effects as layers on top of each other.
+
+use anyhow::Error;
+use openrazer::{Canvas, Color};
+use serde::Deserialize;
+
+use crate::{
+    effects::{Effect, EffectContext},
+    util::lerp,
+};
+
+/// How a layer is combined with the layers below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlendMode {
+    /// Replace what is below where the layer drew, faded in by its coverage & the opacity.
+    #[default]
+    #[serde(alias = "alpha")]
+    Normal,
+    Add,
+    Multiply,
+    Screen,
+    Max,
+}
+
+impl BlendMode {
+    pub fn blend(self, below: Color, above: Color) -> Color {
+        let channel = |a: f32, b: f32| match self {
+            BlendMode::Normal => b,
+            BlendMode::Add => a + b,
+            BlendMode::Multiply => a * b,
+            BlendMode::Screen => 1.0 - (1.0 - a) * (1.0 - b),
+            BlendMode::Max => a.max(b),
+        };
+        Color::new(
+            channel(below.r, above.r),
+            channel(below.g, above.g),
+            channel(below.b, above.b),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct Layer {
+    pub effect: Box<dyn Effect>,
+    pub blend: BlendMode,
+    pub opacity: f32,
+    /// Only these cells are drawn, [`None`] for all of them.
+    pub mask: Option<Vec<(usize, usize)>>,
+    frame: Canvas,
+}
+
+impl Layer {
+    pub fn new(effect: Box<dyn Effect>) -> Self {
+        Self {
+            effect,
+            blend: BlendMode::Normal,
+            opacity: 1.0,
+            mask: None,
+            frame: Canvas::new(0, 0),
+        }
+    }
+
+    pub fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn with_opacity(mut self, opacity: f32) -> Self {
+        self.opacity = opacity.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_mask(mut self, mask: Vec<(usize, usize)>) -> Self {
+        self.mask = Some(mask);
+        self
+    }
+
+    pub fn is_static(&self) -> bool {
+        self.effect.is_static()
+    }
+
+    /// Update the effect & draw it over `canvas`, each cell by the effect's
+    /// [coverage](Effect::coverage) times the opacity.
+    pub fn draw(&mut self, canvas: &mut Canvas, ctx: &EffectContext) -> Result<(), Error> {
+        self.frame.resize(canvas.width(), canvas.height());
+        self.effect.update(&mut self.frame, ctx)?;
+
+        let coverage = self.effect.coverage();
+        let blend = |below: &mut Color, above: &Color| {
+            let (above, alpha) = coverage.split(*above);
+            *below = lerp(
+                *below,
+                self.blend.blend(*below, above),
+                self.opacity * alpha,
+            );
+        };
+        match &self.mask {
+            Some(mask) => {
+                for (x, y) in mask.iter() {
+                    if let Some(above) = self.frame.get(*x, *y)
+                        && let Some(below) = canvas.get_mut(*x, *y)
+                    {
+                        blend(below, above);
+                    }
+                }
+            }
+            None => {
+                for (x, y, below) in canvas.iter_mut() {
+                    if let Some(above) = self.frame.get(x, y) {
+                        blend(below, above);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Layers drawn from bottom to top.
+#[derive(Debug, Default)]
+pub struct Compositor {
+    layers: Vec<Layer>,
+}
+
+impl Compositor {
+    pub fn push(&mut self, layer: Layer) {
+        self.layers.push(layer);
+    }
+
+    pub fn is_static(&self) -> bool {
+        self.layers.iter().all(|layer| layer.is_static())
+    }
+
+    pub fn draw(&mut self, canvas: &mut Canvas, ctx: &EffectContext) -> Result<(), Error> {
+        for layer in self.layers.iter_mut() {
+            layer.draw(canvas, ctx)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, time::Duration};
+
+    use openrazer::{Canvas, Color};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{BlendMode, Compositor, Layer};
+    use crate::{
+        clock::FrameClock,
+        effects::{Coverage, Effect, EffectContext, EffectRainbow1, EffectRipple, EffectSolid},
+        util::lerp,
+    };
+
+    const RED: Color = Color::new(1.0, 0.0, 0.0);
+    const BLUE: Color = Color::new(0.0, 0.0, 1.0);
+
+    /// Draw two frames half a second apart, with the same seed each time.
+    fn frames(mut draw: impl FnMut(&mut Canvas, &EffectContext)) -> Canvas {
+        let rng = RefCell::new(StdRng::seed_from_u64(1));
+        let mut clock = FrameClock::default();
+        let mut canvas = Canvas::new(22, 6);
+        for _ in 0..2 {
+            canvas.fill(Color::new(0.0, 0.0, 0.0));
+            let ctx = EffectContext::test(&rng, clock.step(Duration::from_millis(500)));
+            draw(&mut canvas, &ctx);
+        }
+        canvas
+    }
+
+    fn assert_close(a: Color, b: Color) {
+        assert!(a.difference(b) < 1e-4, "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn coverage() {
+        assert_eq!(Coverage::Full.split(BLUE * 0.5), (BLUE * 0.5, 1.0));
+        assert_eq!(
+            Coverage::Brightness.split(Color::new(0.5, 0.25, 0.0)),
+            (Color::new(1.0, 0.5, 0.0), 0.5)
+        );
+        assert_eq!(Coverage::Brightness.split(Color::new(0.0, 0.0, 0.0)).1, 0.0);
+        assert_eq!(Coverage::Brightness.split(RED * 2.0), (RED * 2.0, 1.0));
+    }
+
+    #[test]
+    fn layer_opacity_mask() {
+        let mut layer = Layer::new(Box::new(EffectSolid::new(RED)))
+            .with_opacity(0.5)
+            .with_mask(vec![(0, 0), (3, 2), (30, 0)]);
+        let canvas = frames(|canvas, ctx| {
+            canvas.fill(BLUE);
+            layer.draw(canvas, ctx).unwrap();
+        });
+        for (x, y, color) in canvas.iter() {
+            let expected = match (x, y) {
+                (0, 0) | (3, 2) => Color::new(0.5, 0.0, 0.5),
+                _ => BLUE,
+            };
+            assert_eq!(*color, expected, "{x},{y}");
+        }
+    }
+
+    #[test]
+    fn compose() {
+        // Ripple over half of a rainbow, over red on one cell.
+        let mut compositor = Compositor::default();
+        compositor.push(Layer::new(Box::new(EffectSolid::new(RED))).with_mask(vec![(0, 0)]));
+        compositor.push(Layer::new(Box::new(EffectRainbow1::new())).with_opacity(0.5));
+        compositor.push(Layer::new(Box::new(EffectRipple::new())));
+        let composed = frames(|canvas, ctx| compositor.draw(canvas, ctx).unwrap());
+
+        let mut rainbow = EffectRainbow1::new();
+        let rainbow = frames(|canvas, ctx| rainbow.update(canvas, ctx).unwrap());
+        let mut ripple = EffectRipple::new();
+        let ripple = frames(|canvas, ctx| ripple.update(canvas, ctx).unwrap());
+
+        let (mut empty, mut drawn) = (0, 0);
+        for (x, y, color) in composed.iter() {
+            let base = if (x, y) == (0, 0) {
+                RED
+            } else {
+                Color::new(0.0, 0.0, 0.0)
+            };
+            let below = lerp(base, *rainbow.get(x, y).unwrap(), 0.5);
+            let (above, alpha) = Coverage::Brightness.split(*ripple.get(x, y).unwrap());
+            match alpha {
+                0.0 => empty += 1,
+                0.5.. => drawn += 1,
+                _ => {}
+            }
+            assert_close(*color, lerp(below, above, alpha));
+        }
+        // The rainbow shows through around the ring.
+        assert!(empty > 0 && drawn > 0, "{empty} empty, {drawn} drawn");
+    }
+
+    #[test]
+    fn blend_modes() {
+        let below = Color::new(0.5, 0.25, 1.0);
+        let above = Color::new(0.5, 0.75, 0.0);
+        assert_eq!(BlendMode::Normal.blend(below, above), above);
+        assert_eq!(
+            BlendMode::Add.blend(below, above),
+            Color::new(1.0, 1.0, 1.0)
+        );
+        assert_eq!(
+            BlendMode::Multiply.blend(below, above),
+            Color::new(0.25, 0.1875, 0.0)
+        );
+        assert_eq!(
+            BlendMode::Screen.blend(below, above),
+            Color::new(0.75, 0.8125, 1.0)
+        );
+        assert_eq!(
+            BlendMode::Max.blend(below, above),
+            Color::new(0.5, 0.75, 1.0)
+        );
+    }
+}
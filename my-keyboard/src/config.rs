@@ -0,0 +1,230 @@
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Error};
+use openrazer::{Color, LayoutKey};
+use serde::{Deserialize, Deserializer};
+
+use crate::{compositor::BlendMode, transition::TransitionKind};
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Layout file to use instead of the built in one for the keyboard.
+    pub layout: Option<PathBuf>,
+    /// Layout variant, e.g. `"iso"`.
+    pub layout_variant: Option<String>,
+    /// Named groups of keys, added to (or replacing) the layout's groups.
+    pub groups: BTreeMap<String, Vec<LayoutKey>>,
+    /// Effects drawn over the current effect, from bottom to top.
+    pub layers: Vec<LayerConfig>,
+    /// Groups drawn over the current effect & layers.
+    pub overlays: Vec<OverlayConfig>,
+    /// Transition between effects, e.g. `"crossfade"`, `"wipe_left"`, `"radial"` or `"none"`.
+    pub transition: TransitionKind,
+    /// Seconds a transition takes.
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub transition_duration: f32,
+    /// Hex color drawn on Caps/Num/Scroll Lock while they are on, e.g. `"#FFFFFF"`.
+    #[serde(deserialize_with = "deserialize_optional_color")]
+    pub lock_color: Option<Color>,
+    /// How fast effects run, `1.0` is real time & `0.0` freezes them.
+    #[serde(deserialize_with = "deserialize_non_negative")]
+    pub time_scale: f32,
+    /// Seed for effects & effect order, random if not set. The seed used is logged on startup.
+    pub seed: Option<u64>,
+    /// Serve frame timing metrics in Prometheus text format here, e.g. `"127.0.0.1:9858"`.
+    pub metrics_address: Option<SocketAddr>,
+    /// Stages after no input, e.g. dimming, then a screensaver, then switching off.
+    pub idle: Vec<IdleStageConfig>,
+    /// Seconds fading between idle stages & waking up takes.
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub idle_fade: f32,
+    /// Effect shown while the session is locked, key presses are never shown while locked.
+    pub locked_effect: Option<String>,
+    /// logind session ID to follow, e.g. `"2"`, instead of our own session. Needed when running
+    /// outside of a session, e.g. as a system service.
+    pub session: Option<String>,
+    /// `0.0` is off & `1.0` is full brightness.
+    #[serde(deserialize_with = "deserialize_fraction")]
+    pub brightness: f32,
+    /// Unix socket for `my-keyboardctl`, see [`crate::protocol::default_socket_path`].
+    pub control_socket: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayerConfig {
+    /// Effect identifier, e.g. `"effect_ripple"`.
+    pub effect: String,
+    #[serde(default)]
+    pub blend: BlendMode,
+    #[serde(default = "default_opacity", deserialize_with = "deserialize_fraction")]
+    pub opacity: f32,
+    /// Only draw the layer on this group.
+    pub group: Option<String>,
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            layout: None,
+            layout_variant: None,
+            groups: BTreeMap::new(),
+            layers: Vec::new(),
+            overlays: Vec::new(),
+            transition: TransitionKind::default(),
+            transition_duration: 1.5,
+            lock_color: None,
+            time_scale: 1.0,
+            seed: None,
+            metrics_address: None,
+            idle: Vec::new(),
+            idle_fade: 2.0,
+            locked_effect: None,
+            session: None,
+            brightness: 1.0,
+            control_socket: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IdleStageConfig {
+    /// Seconds without input before the stage starts.
+    #[serde(deserialize_with = "deserialize_seconds")]
+    pub after: f32,
+    /// `0.0` is off & `1.0` is full brightness.
+    #[serde(
+        default = "default_brightness",
+        deserialize_with = "deserialize_fraction"
+    )]
+    pub brightness: f32,
+    /// Effect shown during the stage, e.g. a screensaver.
+    pub effect: Option<String>,
+}
+
+fn default_brightness() -> f32 {
+    1.0
+}
+
+/// Static color on a group of keys, e.g. red on WASD.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OverlayConfig {
+    pub group: String,
+    /// Hex color, e.g. `"#FF0000"`.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub color: Color,
+}
+
+fn deserialize_optional_color<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Color>, D::Error> {
+    deserialize_color(deserializer).map(Some)
+}
+
+fn deserialize_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let str = String::deserialize(deserializer)?;
+    Color::from_hex(&str).ok_or_else(|| serde::de::Error::custom(format!("Invalid color {str:?}")))
+}
+
+/// Finite number from 0.
+fn deserialize_non_negative<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let value = f32::deserialize(deserializer)?;
+    if !value.is_finite() || value < 0.0 {
+        return Err(serde::de::Error::custom(format!(
+            "Invalid number {value}, must be a finite number from 0"
+        )));
+    }
+    Ok(value)
+}
+
+/// Number from 0 to 1, e.g. a brightness.
+fn deserialize_fraction<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let value = f32::deserialize(deserializer)?;
+    if !(0.0..=1.0).contains(&value) {
+        return Err(serde::de::Error::custom(format!(
+            "Invalid number {value}, must be from 0 to 1"
+        )));
+    }
+    Ok(value)
+}
+
+/// Seconds that fit in a [`std::time::Duration`].
+fn deserialize_seconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let seconds = deserialize_non_negative(deserializer)?;
+    if std::time::Duration::try_from_secs_f32(seconds).is_err() {
+        return Err(serde::de::Error::custom(format!(
+            "Invalid seconds {seconds}, too long"
+        )));
+    }
+    Ok(seconds)
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/my-keyboard/config.toml`
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("my-keyboard").join("config.toml"))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let str = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {path:?}"))?;
+        toml::from_str(&str).with_context(|| format!("Failed to parse config {path:?}"))
+    }
+
+    /// Load the config from `path`, or from the default path if it exists.
+    pub fn load_or_default(path: Option<&Path>) -> Result<Self, Error> {
+        if let Some(path) = path {
+            return Self::load(path);
+        }
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::load(path),
+            _ => Ok(Self::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Config;
+
+    #[test]
+    fn numbers() {
+        let config: Config =
+            toml::from_str("idle_fade = 0.5\n[[idle]]\nafter = 60\nbrightness = 0.5\n").unwrap();
+        assert_eq!(config.idle_fade, 0.5);
+        assert_eq!(config.idle[0].after, 60.0);
+
+        for config in [
+            "idle_fade = inf",
+            "idle_fade = nan",
+            "transition_duration = inf",
+            "transition_duration = -1",
+            "time_scale = inf",
+            "time_scale = -1",
+            "[[idle]]\nafter = inf",
+            "[[idle]]\nafter = -5",
+            "[[idle]]\nafter = 5\nbrightness = nan",
+            "[[idle]]\nafter = 5\nbrightness = 2",
+            "brightness = nan",
+            "brightness = -0.5",
+            "[[layers]]\neffect = \"effect_ripple\"\nopacity = nan",
+        ] {
+            assert!(toml::from_str::<Config>(config).is_err(), "{config}");
+        }
+    }
+}
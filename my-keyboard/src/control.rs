@@ -0,0 +1,287 @@
+//! Control socket of the daemon, see [`crate::protocol`] & `my-keyboardctl`.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        fs::{DirBuilderExt, MetadataExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::{bail, Context, Error};
+use openrazer::Canvas;
+
+use crate::protocol::{self, Request, Response};
+
+/// Request waiting for the main loop to answer it.
+#[derive(Debug)]
+pub struct PendingRequest {
+    pub request: Request,
+    reply: Sender<Response>,
+}
+
+impl PendingRequest {
+    pub fn reply(self, response: Response) {
+        // The client may have disconnected.
+        let _ = self.reply.send(response);
+    }
+}
+
+/// Frames waiting to be written to a subscribed client, slow clients miss frames.
+const FRAME_BACKLOG: usize = 4;
+
+type Subscribers = Arc<Mutex<Vec<SyncSender<Response>>>>;
+
+/// Accepts clients on a background thread, requests are answered with [`ControlServer::try_next`]
+/// from the main loop.
+#[derive(Debug)]
+pub struct ControlServer {
+    path: PathBuf,
+    requests: Receiver<PendingRequest>,
+    /// Clients that sent [`Request::SubscribeFrames`].
+    subscribers: Subscribers,
+}
+
+impl ControlServer {
+    /// Listen on `path`, only the user can connect to it.
+    ///
+    /// The directory of the socket is created private to the user if it doesn't exist, & has to
+    /// be private if it does.
+    pub fn bind(path: &Path) -> Result<Self, Error> {
+        let dir = path
+            .parent()
+            .with_context(|| format!("Invalid control socket {path:?}"))?;
+        if !dir.exists() {
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .with_context(|| format!("Failed to create {dir:?}"))?;
+        }
+        // Other users could connect to the socket between binding & changing its permissions.
+        let metadata = std::fs::metadata(dir)?;
+        if metadata.uid() != protocol::euid() {
+            bail!("{dir:?} for the control socket is owned by another user");
+        }
+        if metadata.mode() & 0o077 != 0 {
+            bail!("{dir:?} for the control socket must only be accessible by its owner (mode 700)");
+        }
+
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                bail!("Control socket {path:?} is already in use, is my-keyboard running?");
+            }
+            // Left over from a daemon that didn't exit cleanly.
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to listen on control socket {path:?}"))?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        log::info!("Listening on control socket {path:?}");
+
+        let (sender, requests) = mpsc::channel();
+        let subscribers = Subscribers::default();
+        let weak_subscribers = Arc::downgrade(&subscribers);
+        std::thread::Builder::new()
+            .name("control".to_owned())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    // The server was dropped.
+                    let Some(subscribers) = weak_subscribers.upgrade() else {
+                        break;
+                    };
+                    let sender = sender.clone();
+                    std::thread::spawn(move || {
+                        if let Err(err) = serve(stream, sender, subscribers) {
+                            log::debug!("Control client failed: {err}");
+                        }
+                    });
+                }
+            })?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            requests,
+            subscribers,
+        })
+    }
+
+    pub fn try_next(&self) -> Option<PendingRequest> {
+        self.requests.try_recv().ok()
+    }
+
+    /// Send a frame to the clients that subscribed to frames.
+    pub fn publish_frame(&self, canvas: &Canvas) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        let frame = frame_response(canvas);
+        subscribers.retain(|subscriber| {
+            !matches!(
+                subscriber.try_send(frame.clone()),
+                Err(TrySendError::Disconnected(_))
+            )
+        });
+    }
+}
+
+/// [`Response::Frame`] of a canvas.
+pub fn frame_response(canvas: &Canvas) -> Response {
+    Response::Frame {
+        width: canvas.width(),
+        height: canvas.height(),
+        colors: canvas
+            .iter()
+            .map(|(_, _, color)| color.to_hex().split_off(1))
+            .collect(),
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        // Ends the subscribed clients.
+        self.subscribers.lock().unwrap().clear();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Answer requests from a client until it disconnects, or send it frames once it subscribes.
+fn serve(
+    stream: UnixStream,
+    requests: Sender<PendingRequest>,
+    subscribers: Subscribers,
+) -> Result<(), Error> {
+    let mut writer = &stream;
+    for line in BufReader::new(&stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::SubscribeFrames) => {
+                let (sender, frames) = mpsc::sync_channel(FRAME_BACKLOG);
+                subscribers.lock().unwrap().push(sender);
+                drop(subscribers);
+                serde_json::to_writer(writer, &Response::Ok)?;
+                writer.write_all(b"\n")?;
+                // Ends when the server is dropped.
+                for frame in frames {
+                    serde_json::to_writer(writer, &frame)?;
+                    writer.write_all(b"\n")?;
+                }
+                return Ok(());
+            }
+            Ok(request) => {
+                let (reply, response) = mpsc::channel();
+                requests.send(PendingRequest { request, reply })?;
+                response.recv()?
+            }
+            Err(err) => Response::Error {
+                message: format!("Invalid request: {err}"),
+            },
+        };
+        serde_json::to_writer(writer, &response)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::{fs::PermissionsExt, net::UnixStream},
+    };
+
+    use openrazer::{Canvas, Color};
+
+    use super::ControlServer;
+    use crate::protocol::{Request, Response};
+
+    #[test]
+    fn requests() {
+        let dir = std::env::temp_dir().join(format!("my-keyboard-test-{}", std::process::id()));
+        let path = dir.join("control.sock");
+        let server = ControlServer::bind(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(ControlServer::bind(&path).is_err());
+
+        let stream = UnixStream::connect(&path).unwrap();
+        let mut lines = BufReader::new(&stream).lines();
+        (&stream)
+            .write_all(b"{\"command\":\"set_effect\",\"effect\":\"effect_glow\"}\n{\"command\":\"nope\"}\n")
+            .unwrap();
+        let pending = loop {
+            if let Some(pending) = server.try_next() {
+                break pending;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        };
+        assert_eq!(
+            pending.request,
+            Request::SetEffect {
+                effect: "effect_glow".to_owned()
+            }
+        );
+        pending.reply(Response::Ok);
+        assert_eq!(lines.next().unwrap().unwrap(), "{\"result\":\"ok\"}");
+        let response = lines.next().unwrap().unwrap();
+        assert!(matches!(
+            serde_json::from_str(&response).unwrap(),
+            Response::Error { .. }
+        ));
+
+        drop(server);
+        assert!(!path.exists());
+
+        // Other users could connect before the socket's permissions are changed.
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(ControlServer::bind(&path).is_err());
+        assert!(!path.exists());
+        std::fs::remove_dir(dir).unwrap();
+    }
+
+    #[test]
+    fn frames() {
+        let dir = std::env::temp_dir().join(format!("my-keyboard-frames-{}", std::process::id()));
+        let path = dir.join("control.sock");
+        let server = ControlServer::bind(&path).unwrap();
+        let mut canvas = Canvas::new(2, 1);
+        // Nobody is subscribed yet.
+        server.publish_frame(&canvas);
+
+        let stream = UnixStream::connect(&path).unwrap();
+        let mut lines = BufReader::new(&stream).lines();
+        (&stream)
+            .write_all(b"{\"command\":\"subscribe_frames\"}\n")
+            .unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "{\"result\":\"ok\"}");
+
+        canvas.fill(Color::new(1.0, 0.0, 0.0));
+        server.publish_frame(&canvas);
+        let response = lines.next().unwrap().unwrap();
+        assert_eq!(
+            serde_json::from_str::<Response>(&response).unwrap(),
+            Response::Frame {
+                width: 2,
+                height: 1,
+                colors: "FF0000FF0000".to_owned(),
+            }
+        );
+
+        // Subscribers are dropped with the server.
+        drop(server);
+        assert!(lines.next().is_none());
+        std::fs::remove_dir(dir).unwrap();
+    }
+}
@@ -1,33 +1,75 @@
+use std::time::Duration;
+
 use anyhow::Error;
-use openrazer::DeviceMatrixCustom;
+use openrazer::{Canvas, Color};
+use rand::Rng;
 
-use crate::effects::{Effect, MatrixInput};
+use crate::{
+    compositor::{Compositor, Layer},
+    effects::{Effect, EffectContext, MatrixInputKind},
+    transition::{Transition, TransitionKind, Transitioning},
+};
 
-pub struct EffectCycler<'a> {
-    matrix: DeviceMatrixCustom<'a>,
+pub struct EffectCycler {
     effect: Option<Box<dyn Effect>>,
     effect_creators: Vec<Box<dyn Fn() -> Box<dyn Effect>>>,
+    /// Layers drawn over the current effect.
+    layers: Compositor,
+    /// Drawn on the keys of locks that are on, [`None`] to not show locks.
+    lock_color: Option<Color>,
+    transition_kind: TransitionKind,
+    transition_duration: Duration,
+    transition: Option<Transition>,
+    /// Where the last key press was, for radial transitions.
+    last_press: Option<(f32, f32)>,
 }
 
-impl<'a> std::fmt::Debug for EffectCycler<'a> {
+impl std::fmt::Debug for EffectCycler {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("EffectCycler")
-            .field("matrix", &self.matrix)
             .field("effect", &self.effect)
+            .field("layers", &self.layers)
+            .field("lock_color", &self.lock_color)
+            .field("transition", &self.transition)
             // .field("effect_creators", &self.effect_creators)
             .finish()
     }
 }
 
-impl<'a> EffectCycler<'a> {
-    pub fn new(matrix: DeviceMatrixCustom<'a>) -> Self {
+impl Default for EffectCycler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EffectCycler {
+    pub fn new() -> Self {
         Self {
-            matrix,
             effect: None,
             effect_creators: Vec::new(),
+            layers: Compositor::default(),
+            lock_color: None,
+            transition_kind: TransitionKind::None,
+            transition_duration: Duration::ZERO,
+            transition: None,
+            last_press: None,
         }
     }
 
+    /// Transition used when the effect changes.
+    pub fn set_transition(&mut self, kind: TransitionKind, duration: Duration) {
+        self.transition_kind = kind;
+        self.transition_duration = duration;
+    }
+
+    pub fn set_lock_color(&mut self, color: Option<Color>) {
+        self.lock_color = color;
+    }
+
+    pub fn add_layer(&mut self, layer: Layer) {
+        self.layers.push(layer);
+    }
+
     pub fn add_effect<F>(&mut self, creator: F)
     where
         F: Fn() -> Box<dyn Effect> + 'static,
@@ -35,47 +77,180 @@ impl<'a> EffectCycler<'a> {
         self.effect_creators.push(Box::new(creator));
     }
 
-    pub fn update(&mut self, events: &[MatrixInput]) -> Result<(), Error> {
-        if let Some(effect) = self.effect.as_mut() {
-            effect.update(&mut self.matrix, events)?;
+    /// If frames only change when there are inputs, see [`Effect::is_static`].
+    pub fn is_static(&self) -> bool {
+        self.effect.as_ref().is_none_or(|effect| effect.is_static())
+            && self.transition.is_none()
+            && self.layers.is_static()
+    }
+
+    /// Draw the next frame.
+    pub fn render(&mut self, canvas: &mut Canvas, ctx: &EffectContext) -> Result<(), Error> {
+        if let Some(pos) = ctx
+            .inputs
+            .iter()
+            .filter(|input| input.kind == MatrixInputKind::Pressed)
+            .rev()
+            .find_map(|input| ctx.mapper.center(&input.cells))
+        {
+            self.last_press = Some(pos);
+        }
+
+        match self.effect.as_mut() {
+            Some(effect) => effect.update(canvas, ctx)?,
+            None => canvas.fill(Color::new(0.0, 0.0, 0.0)),
+        }
+        if let Some(transition) = self.transition.as_mut() {
+            transition.update(canvas, ctx)?;
+            if transition.is_done(ctx.time.elapsed) {
+                self.transition = None;
+            }
+        }
+        self.layers.draw(canvas, ctx)?;
+        if let Some(lock_color) = self.lock_color {
+            for key in ctx.locks.keys() {
+                for (x, y) in ctx.mapper.cells(key) {
+                    if let Some(color) = canvas.get_mut(*x, *y) {
+                        *color = lock_color;
+                    }
+                }
+            }
         }
         Ok(())
     }
 
-    pub fn next_effect(&mut self) {
+    /// Start a transition from the current frame, if there is an effect or a transition.
+    ///
+    /// A transition that is still running keeps going underneath the new one, so there is no jump.
+    fn start_transition(&mut self) {
+        let effect = self.effect.take();
+        let running = self.transition.take();
+        if self.transition_kind == TransitionKind::None {
+            return;
+        }
+        let from: Box<dyn Effect> = match (running, effect) {
+            (Some(transition), effect) => Box::new(Transitioning { effect, transition }),
+            (None, Some(effect)) => effect,
+            (None, None) => return,
+        };
+        self.transition = Some(Transition::new(
+            self.transition_kind,
+            self.transition_duration,
+            from,
+            self.last_press,
+        ));
+    }
+
+    pub fn next_effect(&mut self, rng: &mut impl Rng) {
         assert!(!self.effect_creators.is_empty());
         let ident = self
             .effect
             .as_ref()
             .map(|effect| effect.identifier().to_owned());
-        loop {
-            let creator = &self.effect_creators[rand::random_range(0..self.effect_creators.len())];
-            self.effect = Some(creator());
-            if self.effect_creators.len() == 1
-                || self.effect.as_ref().map(|effect| effect.identifier())
-                    != ident.as_ref().map(|s| s.as_str())
-            {
-                break;
+        let effect = loop {
+            let creator = &self.effect_creators[rng.random_range(0..self.effect_creators.len())];
+            let effect = creator();
+            if self.effect_creators.len() == 1 || Some(effect.identifier()) != ident.as_deref() {
+                break effect;
             }
-        }
+        };
+        self.start_transition();
+        self.effect = Some(effect);
     }
 
     pub fn set_no_effect(&mut self) {
+        self.start_transition();
         self.effect = None;
     }
 
     pub fn set_effect(&mut self, effect_identifier: &str) -> bool {
-        for creator in self.effect_creators.iter() {
-            let effect = creator();
-            if effect.identifier() == effect_identifier {
-                self.effect = Some(effect);
-                return true;
-            }
-        }
-        false
+        let Some(effect) = self.create_effect(effect_identifier) else {
+            return false;
+        };
+        self.start_transition();
+        self.effect = Some(effect);
+        true
+    }
+
+    /// New instance of an added effect, e.g. for a layer.
+    pub fn create_effect(&self, effect_identifier: &str) -> Option<Box<dyn Effect>> {
+        self.effect_creators
+            .iter()
+            .map(|creator| creator())
+            .find(|effect| effect.identifier() == effect_identifier)
+    }
+
+    /// Identifiers of all added effects.
+    pub fn effect_identifiers(&self) -> Vec<String> {
+        self.effect_creators
+            .iter()
+            .map(|creator| creator().identifier().to_owned())
+            .collect()
     }
 
     pub fn current_effect_identifier(&self) -> Option<&str> {
         self.effect.as_ref().map(|effect| effect.identifier())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, time::Duration};
+
+    use openrazer::{Canvas, Color, KeyCode, MatrixMapper};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::EffectCycler;
+    use crate::{
+        clock::FrameClock,
+        effects::{EffectContext, LockState},
+    };
+
+    fn render(cycler: &mut EffectCycler, locks: LockState) -> Canvas {
+        let mut mapper = MatrixMapper::default();
+        mapper.add_mappings([
+            (KeyCode::KEY_CAPSLOCK, (0, 3)),
+            (KeyCode::KEY_NUMLOCK, (18, 1)),
+            (KeyCode::KEY_A, (1, 3)),
+        ]);
+        let rng = RefCell::new(StdRng::seed_from_u64(0));
+        let ctx = EffectContext {
+            locks,
+            mapper: &mapper,
+            ..EffectContext::test(&rng, FrameClock::default().step(Duration::from_millis(16)))
+        };
+        let mut canvas = Canvas::new(22, 6);
+        cycler.render(&mut canvas, &ctx).unwrap();
+        canvas
+    }
+
+    fn lit(canvas: &Canvas) -> Vec<(usize, usize)> {
+        canvas
+            .iter()
+            .filter(|(_, _, color)| **color != Color::new(0.0, 0.0, 0.0))
+            .map(|(x, y, _)| (x, y))
+            .collect()
+    }
+
+    #[test]
+    fn lock_overlay() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        let caps = LockState {
+            caps: true,
+            ..Default::default()
+        };
+        let mut cycler = EffectCycler::new();
+
+        // Locks aren't shown without a color.
+        assert!(lit(&render(&mut cycler, caps)).is_empty());
+
+        cycler.set_lock_color(Some(white));
+        let canvas = render(&mut cycler, caps);
+        assert_eq!(lit(&canvas), [(0, 3)]);
+        assert_eq!(canvas.get(0, 3), Some(&white));
+
+        let both = LockState { num: true, ..caps };
+        assert_eq!(lit(&render(&mut cycler, both)), [(18, 1), (0, 3)]);
+        assert!(lit(&render(&mut cycler, LockState::default())).is_empty());
+    }
+}
@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use anyhow::Error;
+use openrazer::{Canvas, Color};
+
+use crate::util::simple_ease;
+
+use super::{Coverage, Effect, EffectContext};
+
+/// Seconds a key has to be held to fully light up
+const GLOW_RISE_DURATION: f32 = 0.75;
+/// Seconds a key takes to fade out after release
+const GLOW_FADE_DURATION: f32 = 1.5;
+
+#[derive(Debug)]
+pub struct EffectGlow {
+    start: Option<Duration>,
+}
+
+impl EffectGlow {
+    pub fn new() -> Self {
+        Self { start: None }
+    }
+}
+
+impl Effect for EffectGlow {
+    fn identifier(&self) -> &str {
+        "effect_glow"
+    }
+
+    fn coverage(&self) -> Coverage {
+        Coverage::Brightness
+    }
+
+    fn update(&mut self, matrix: &mut Canvas, ctx: &EffectContext) -> Result<(), Error> {
+        let now = ctx.time.elapsed;
+        let time = ctx.time.since(&mut self.start);
+
+        let width = matrix.width() as f32;
+        matrix.iter_mut().for_each(|(_x, _y, color)| {
+            *color = Color::new(0.0, 0.0, 0.0);
+        });
+
+        for (_key, state) in ctx.keys.iter() {
+            let amount = 0.2
+                + 0.8 * (state.held_for(now).as_secs_f32() / GLOW_RISE_DURATION).clamp(0.0, 1.0);
+            let amount = match state.since_release(now) {
+                Some(since) => {
+                    amount
+                        * (1.0
+                            - simple_ease(
+                                (since.as_secs_f32() / GLOW_FADE_DURATION).clamp(0.0, 1.0),
+                            ))
+                }
+                None => amount,
+            };
+
+            for (x, y) in state.cells.iter() {
+                if let Some(color) = matrix.get_mut(*x, *y) {
+                    let hue = (*x as f32 / width) * 360.0 + time.as_secs_f32() * 20.0;
+                    *color += Color::from_hsl(hue, 1.0, 0.5) * amount;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,9 +1,12 @@
+use std::time::Duration;
+
 use anyhow::Error;
-use openrazer::{Color, DeviceMatrixCustom, MATRIX_HEIGHT, MATRIX_WIDTH};
+use openrazer::{Canvas, Color};
+use rand::Rng;
 
 use crate::util::simple_ease;
 
-use super::{Effect, MatrixInput};
+use super::{Coverage, Effect, EffectContext};
 
 /// Seconds until line starts decaying
 const LINE_DECAY_OFFSET: f32 = 0.5;
@@ -16,29 +19,23 @@ struct Line {
     x: f32,
     y: f32,
     angle: f32,
-    start: std::time::Instant,
+    start: Duration,
 }
 
 impl Line {
-    fn new(x: f32, y: f32, angle: f32) -> Self {
+    fn new(rng: &mut impl Rng, start: Duration, x: f32, y: f32, angle: f32) -> Self {
         Self {
-            color: Color::from_hsl(rand::random::<f32>() * 360.0, 1.0, 0.5),
+            color: Color::from_hsl(rng.random::<f32>() * 360.0, 1.0, 0.5),
             x,
             y,
             angle,
-            start: std::time::Instant::now(),
+            start,
         }
     }
 
-    fn new_random_angle(x: f32, y: f32) -> Self {
-        Self::new(x, y, rand::random::<f32>() * std::f32::consts::TAU)
-    }
-
-    fn new_random_pos_angle() -> Self {
-        Self::new_random_angle(
-            rand::random::<f32>() * MATRIX_WIDTH as f32,
-            rand::random::<f32>() * MATRIX_HEIGHT as f32,
-        )
+    fn new_random_angle(rng: &mut impl Rng, start: Duration, x: f32, y: f32) -> Self {
+        let angle = rng.random::<f32>() * std::f32::consts::TAU;
+        Self::new(rng, start, x, y, angle)
     }
 
     fn dist(&self, x: f32, y: f32) -> f32 {
@@ -54,9 +51,7 @@ pub struct EffectLine {
 
 impl EffectLine {
     pub fn new() -> Self {
-        Self {
-            lines: vec![Line::new_random_pos_angle()],
-        }
+        Self { lines: Vec::new() }
     }
 }
 
@@ -65,33 +60,37 @@ impl Effect for EffectLine {
         "effect_line"
     }
 
-    fn update<'a, 'b>(
-        &mut self,
-        matrix: &'b mut DeviceMatrixCustom<'a>,
-        inputs: &[MatrixInput],
-    ) -> Result<(), Error> {
-        let now = std::time::Instant::now();
-
-        for input in inputs {
-            // if let MatrixInput::Pressed { x, y } = input {
-            //     self.lines
-            //         .push(Line::new_random_angle(*x as f32, *y as f32));
+    fn coverage(&self) -> Coverage {
+        Coverage::Brightness
+    }
+
+    fn update(&mut self, matrix: &mut Canvas, ctx: &EffectContext) -> Result<(), Error> {
+        let now = ctx.time.elapsed;
+
+        for input in ctx.inputs {
+            // if input.kind == MatrixInputKind::Pressed
+            //     && let Some((x, y)) = ctx.mapper.center(&input.cells)
+            // {
+            //     self.lines.push(Line::new_random_angle(&mut *ctx.rng(), now, x, y));
             // }
-            self.lines
-                .push(Line::new_random_angle(input.x() as f32, input.y() as f32));
+            if let Some((x, y)) = ctx.mapper.center(&input.cells) {
+                self.lines
+                    .push(Line::new_random_angle(&mut *ctx.rng(), now, x, y));
+            }
         }
 
         self.lines.retain(|line| {
-            now.duration_since(line.start).as_secs_f32() <= LINE_DECAY_OFFSET + LINE_DECAY_DURATION
+            now.saturating_sub(line.start).as_secs_f32() <= LINE_DECAY_OFFSET + LINE_DECAY_DURATION
         });
 
         if self.lines.is_empty() {
-            self.lines.push(Line::new_random_pos_angle());
+            let (x, y) = ctx.random_cell();
+            self.lines
+                .push(Line::new_random_angle(&mut *ctx.rng(), now, x, y));
         }
 
         matrix.iter_mut().for_each(|(x, y, color)| {
-            let x = x as f32;
-            let y = y as f32;
+            let (x, y) = ctx.mapper.position(x, y);
 
             *color = self
                 .lines
@@ -99,7 +98,7 @@ impl Effect for EffectLine {
                 .map(|line| {
                     let dist = line.dist(x, y);
                     let amount = 1.0 - simple_ease((dist / 1.5).clamp(0.0, 1.0));
-                    let t = now.duration_since(line.start);
+                    let t = now.saturating_sub(line.start);
                     let amount = if t.as_secs_f32() <= LINE_DECAY_OFFSET {
                         amount
                     } else {
@@ -111,8 +110,6 @@ impl Effect for EffectLine {
                 .fold(Color::new(0.0, 0.0, 0.0), |acc, col| acc + col)
         });
 
-        matrix.send_update()?;
-
         Ok(())
     }
 }
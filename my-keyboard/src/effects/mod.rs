@@ -1,52 +1,117 @@
-use std::fmt::Debug;
+use std::{
+    cell::{RefCell, RefMut},
+    fmt::Debug,
+};
 
 use anyhow::Error;
-use openrazer::DeviceMatrixCustom;
+use openrazer::{Canvas, Color, MatrixMapper};
+use rand::{rngs::StdRng, Rng};
 
-use crate::cycler::EffectCycler;
+pub use crate::input::{KeyStates, LockState, MatrixInput, MatrixInputKind};
+use crate::{clock::FrameTime, cycler::EffectCycler};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum MatrixInput {
-    Pressed { x: usize, y: usize },
-    Released { x: usize, y: usize },
-    Repeat { x: usize, y: usize },
+/// Everything an effect gets each frame besides the matrix.
+#[derive(Debug)]
+pub struct EffectContext<'a> {
+    /// Inputs since the last frame.
+    pub inputs: &'a [MatrixInput],
+    pub keys: &'a KeyStates,
+    pub locks: LockState,
+    pub mapper: &'a MatrixMapper,
+    /// Time of this frame, effects should use this instead of the system clock.
+    pub time: FrameTime,
+    /// Shared random source, seeded so runs can be replayed.
+    pub rng: &'a RefCell<StdRng>,
 }
 
-impl MatrixInput {
-    pub fn x(&self) -> usize {
-        match self {
-            MatrixInput::Pressed { x, .. } => *x,
-            MatrixInput::Released { x, .. } => *x,
-            MatrixInput::Repeat { x, .. } => *x,
+impl EffectContext<'_> {
+    /// Effects should use this instead of [`rand::random`], don't hold it across calls that also
+    /// use it (e.g. [`Self::random_cell`]).
+    pub fn rng(&self) -> RefMut<'_, StdRng> {
+        self.rng.borrow_mut()
+    }
+
+    /// Position of a random cell that has a key, or anywhere on the matrix if there is no mapping.
+    pub fn random_cell(&self) -> (f32, f32) {
+        let cells = self.mapper.populated().collect::<Vec<_>>();
+        let mut rng = self.rng();
+        if cells.is_empty() {
+            let ((min_x, min_y), (max_x, max_y)) = self.mapper.bounds();
+            return (
+                rng.random_range(min_x..=max_x),
+                rng.random_range(min_y..=max_y),
+            );
         }
+        let (x, y) = cells[rng.random_range(0..cells.len())];
+        self.mapper.position(x, y)
     }
+}
 
-    pub fn y(&self) -> usize {
-        match self {
-            MatrixInput::Pressed { y, .. } => *y,
-            MatrixInput::Released { y, .. } => *y,
-            MatrixInput::Repeat { y, .. } => *y,
+#[cfg(test)]
+impl<'a> EffectContext<'a> {
+    /// Context without inputs or held keys on the default mapper, for tests.
+    pub fn test(rng: &'a RefCell<StdRng>, time: FrameTime) -> Self {
+        static MAPPER: std::sync::LazyLock<MatrixMapper> =
+            std::sync::LazyLock::new(MatrixMapper::default);
+        static KEYS: std::sync::LazyLock<KeyStates> = std::sync::LazyLock::new(KeyStates::default);
+        Self {
+            inputs: &[],
+            keys: &KEYS,
+            locks: LockState::default(),
+            mapper: &MAPPER,
+            time,
+            rng,
         }
     }
+}
 
-    pub fn pos(&self) -> (usize, usize) {
-        (self.x(), self.y())
+/// Which cells of a frame an effect drew, for layers to let the layers below show through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coverage {
+    /// Every cell is drawn.
+    Full,
+    /// Black is empty, brighter cells are drawn more, e.g. ripples drawn on black.
+    Brightness,
+}
+
+impl Coverage {
+    /// Color of a cell without the coverage, & how much of the cell is drawn from 0 to 1.
+    pub fn split(self, color: Color) -> (Color, f32) {
+        match self {
+            Coverage::Full => (color, 1.0),
+            Coverage::Brightness => {
+                let alpha = color.r.max(color.g).max(color.b).clamp(0.0, 1.0);
+                if alpha <= 0.0 {
+                    (color, 0.0)
+                } else {
+                    (color / alpha, alpha)
+                }
+            }
+        }
     }
 }
 
 pub trait Effect: Debug {
     fn identifier(&self) -> &str;
 
+    /// If frames only change when there are inputs, so the main loop can sleep until the next one.
+    fn is_static(&self) -> bool {
+        false
+    }
+
+    /// Which cells of the frame are drawn when the effect is a layer.
+    fn coverage(&self) -> Coverage {
+        Coverage::Full
+    }
+
+    /// Draw the next frame, the matrix is sent to the keyboard afterwards.
     #[allow(unused)]
-    fn update<'a, 'b>(
-        &mut self,
-        matrix: &'b mut DeviceMatrixCustom<'a>,
-        inputs: &[MatrixInput],
-    ) -> Result<(), Error> {
+    fn update(&mut self, matrix: &mut Canvas, ctx: &EffectContext) -> Result<(), Error> {
         Ok(())
     }
 }
 
+mod glow;
 mod line;
 mod particles;
 mod pride;
@@ -55,7 +120,11 @@ mod rainbow2;
 mod rainbow3;
 mod random;
 mod ripple;
+#[cfg(test)]
+mod snapshot;
+mod solid;
 
+pub use glow::EffectGlow;
 pub use line::EffectLine;
 pub use particles::EffectParticles;
 pub use pride::EffectPride;
@@ -64,8 +133,9 @@ pub use rainbow2::EffectRainbow2;
 pub use rainbow3::EffectRainbow3;
 pub use random::EffectRandom;
 pub use ripple::EffectRipple;
+pub use solid::EffectSolid;
 
-pub fn add_effects_to_cycler(effect_cycler: &mut EffectCycler<'_>) {
+pub fn add_effects_to_cycler(effect_cycler: &mut EffectCycler) {
     effect_cycler.add_effect(|| Box::new(EffectRainbow1::new()));
     effect_cycler.add_effect(|| Box::new(EffectRainbow2::new()));
     effect_cycler.add_effect(|| Box::new(EffectRainbow3::new()));
@@ -74,4 +144,34 @@ pub fn add_effects_to_cycler(effect_cycler: &mut EffectCycler<'_>) {
     effect_cycler.add_effect(|| Box::new(EffectRipple::new()));
     effect_cycler.add_effect(|| Box::new(EffectLine::new()));
     effect_cycler.add_effect(|| Box::new(EffectParticles::new()));
+    effect_cycler.add_effect(|| Box::new(EffectGlow::new()));
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, time::Duration};
+
+    use openrazer::Canvas;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{Effect, EffectContext, EffectParticles};
+    use crate::clock::FrameClock;
+
+    fn render(seed: u64) -> Canvas {
+        let rng = RefCell::new(StdRng::seed_from_u64(seed));
+        let mut clock = FrameClock::default();
+        let mut effect = EffectParticles::new();
+        let mut canvas = Canvas::new(22, 6);
+        for _ in 0..30 {
+            let ctx = EffectContext::test(&rng, clock.step(Duration::from_millis(500)));
+            effect.update(&mut canvas, &ctx).unwrap();
+        }
+        canvas
+    }
+
+    #[test]
+    fn seeded() {
+        assert_eq!(render(1), render(1));
+        assert_ne!(render(1), render(2));
+    }
 }
@@ -1,9 +1,12 @@
+use std::time::Duration;
+
 use anyhow::Error;
-use openrazer::{Color, DeviceMatrixCustom, MATRIX_HEIGHT, MATRIX_WIDTH};
+use openrazer::{Canvas, Color};
+use rand::Rng;
 
 use crate::util::simple_ease;
 
-use super::{Effect, MatrixInput};
+use super::{Coverage, Effect, EffectContext, MatrixInputKind};
 
 /// Seconds until the particle starts decaying
 const PARTICLE_DECAY_OFFSET: f32 = 3.0;
@@ -20,28 +23,21 @@ struct Particle {
     y: f32,
     vx: f32,
     vy: f32,
-    start: std::time::Instant,
+    start: Duration,
 }
 
 impl Particle {
-    fn new(x: f32, y: f32) -> Self {
+    fn new(rng: &mut impl Rng, start: Duration, x: f32, y: f32) -> Self {
         Self {
-            color: Color::from_hsl(rand::random::<f32>() * 360.0, 1.0, 0.5),
+            color: Color::from_hsl(rng.random::<f32>() * 360.0, 1.0, 0.5),
             x,
             y,
-            vx: rand::random::<f32>() * 20.0 - 10.0,
-            vy: rand::random::<f32>() * 20.0 - 10.0,
-            start: std::time::Instant::now(),
+            vx: rng.random::<f32>() * 20.0 - 10.0,
+            vy: rng.random::<f32>() * 20.0 - 10.0,
+            start,
         }
     }
 
-    fn new_random() -> Self {
-        Self::new(
-            rand::random::<f32>() * MATRIX_WIDTH as f32,
-            rand::random::<f32>() * MATRIX_HEIGHT as f32,
-        )
-    }
-
     fn dist(&self, x: f32, y: f32) -> f32 {
         f32::sqrt((x - self.x).powi(2) + (y - self.y).powi(2))
     }
@@ -50,18 +46,16 @@ impl Particle {
 #[derive(Debug)]
 pub struct EffectParticles {
     particles: Vec<Particle>,
-    last_update: Option<std::time::Instant>,
-    time_since_last_input: std::time::Instant,
-    time_since_last_autospawn: std::time::Instant,
+    last_input: Option<Duration>,
+    last_autospawn: Option<Duration>,
 }
 
 impl EffectParticles {
     pub fn new() -> Self {
         Self {
             particles: Vec::new(),
-            last_update: None,
-            time_since_last_input: std::time::Instant::now(),
-            time_since_last_autospawn: std::time::Instant::now(),
+            last_input: None,
+            last_autospawn: None,
         }
     }
 }
@@ -71,61 +65,58 @@ impl Effect for EffectParticles {
         "effect_particles"
     }
 
-    fn update<'a, 'b>(
-        &mut self,
-        matrix: &'b mut DeviceMatrixCustom<'a>,
-        inputs: &[MatrixInput],
-    ) -> Result<(), Error> {
-        let now = std::time::Instant::now();
-        let dt = if let Some(last_update) = self.last_update {
-            now.duration_since(last_update).as_secs_f32()
-        } else {
-            0.0
-        };
-
-        self.last_update = Some(now);
-
-        for input in inputs {
-            if let MatrixInput::Pressed { x, y } = input {
-                self.particles.push(Particle::new(*x as f32, *y as f32));
+    fn coverage(&self) -> Coverage {
+        Coverage::Brightness
+    }
+
+    fn update(&mut self, matrix: &mut Canvas, ctx: &EffectContext) -> Result<(), Error> {
+        let now = ctx.time.elapsed;
+        let dt = ctx.time.dt_secs();
+
+        for input in ctx.inputs {
+            if input.kind == MatrixInputKind::Pressed
+                && let Some((x, y)) = ctx.mapper.center(&input.cells)
+            {
+                self.particles
+                    .push(Particle::new(&mut *ctx.rng(), now, x, y));
             }
         }
 
-        if !inputs.is_empty() {
-            self.time_since_last_input = now;
+        if !ctx.inputs.is_empty() {
+            self.last_input = Some(now);
         }
 
-        if now.duration_since(self.time_since_last_input).as_secs_f32()
-            >= NO_INPUT_AUTOSPAWN_PARTICLES_TIME
-            && now
-                .duration_since(self.time_since_last_autospawn)
-                .as_secs_f32()
+        if ctx.time.since(&mut self.last_input).as_secs_f32() >= NO_INPUT_AUTOSPAWN_PARTICLES_TIME
+            && ctx.time.since(&mut self.last_autospawn).as_secs_f32()
                 >= NO_INPUT_AUTOSPAWN_PARTICLES_DELAY
         {
-            self.particles.push(Particle::new_random());
-            self.time_since_last_autospawn = now;
+            let (x, y) = ctx.random_cell();
+            self.particles
+                .push(Particle::new(&mut *ctx.rng(), now, x, y));
+            self.last_autospawn = Some(now);
         }
 
         self.particles.retain(|particle| {
-            now.duration_since(particle.start).as_secs_f32()
+            now.saturating_sub(particle.start).as_secs_f32()
                 <= PARTICLE_DECAY_OFFSET + PARTICLE_DECAY_DURATION
         });
 
+        let ((min_x, min_y), (max_x, max_y)) = ctx.mapper.bounds();
         self.particles.iter_mut().for_each(|particle| {
-            if particle.x < 0.0 {
-                particle.x = 0.0;
+            if particle.x < min_x {
+                particle.x = min_x;
                 particle.vx = f32::abs(particle.vx);
             }
-            if particle.y < 0.0 {
-                particle.y = 0.0;
+            if particle.y < min_y {
+                particle.y = min_y;
                 particle.vy = f32::abs(particle.vy);
             }
-            if particle.x > MATRIX_WIDTH as f32 - 1.0 {
-                particle.x = MATRIX_WIDTH as f32 - 1.0;
+            if particle.x > max_x {
+                particle.x = max_x;
                 particle.vx = -f32::abs(particle.vx);
             }
-            if particle.y > MATRIX_HEIGHT as f32 - 1.0 {
-                particle.y = MATRIX_HEIGHT as f32 - 1.0;
+            if particle.y > max_y {
+                particle.y = max_y;
                 particle.vy = -f32::abs(particle.vy);
             }
             particle.x += particle.vx * dt;
@@ -133,8 +124,7 @@ impl Effect for EffectParticles {
         });
 
         matrix.iter_mut().for_each(|(x, y, color)| {
-            let x = x as f32;
-            let y = y as f32;
+            let (x, y) = ctx.mapper.position(x, y);
 
             *color = self
                 .particles
@@ -142,7 +132,7 @@ impl Effect for EffectParticles {
                 .map(|particle| {
                     let dist = particle.dist(x, y);
                     let amount = 1.0 - simple_ease((dist / 1.5).clamp(0.0, 1.0));
-                    let t = now.duration_since(particle.start);
+                    let t = now.saturating_sub(particle.start);
                     let amount = if t.as_secs_f32() <= PARTICLE_DECAY_OFFSET {
                         amount
                     } else {
@@ -156,8 +146,6 @@ impl Effect for EffectParticles {
                 .fold(Color::new(0.0, 0.0, 0.0), |acc, col| acc + col)
         });
 
-        matrix.send_update()?;
-
         Ok(())
     }
 }
@@ -1,11 +1,13 @@
 use std::sync::LazyLock;
 
+use std::time::Duration;
+
 use anyhow::Error;
-use openrazer::{Color, DeviceMatrixCustom, MATRIX_WIDTH};
+use openrazer::{Canvas, Color};
 
 use crate::util::{lerp, simple_ease};
 
-use super::{Effect, MatrixInput};
+use super::{Effect, EffectContext};
 
 const SCALE: f32 = 0.15;
 
@@ -45,14 +47,12 @@ fn get_color(percent: f32) -> Color {
 
 #[derive(Debug)]
 pub struct EffectPride {
-    start: std::time::Instant,
+    start: Option<Duration>,
 }
 
 impl EffectPride {
     pub fn new() -> Self {
-        Self {
-            start: std::time::Instant::now(),
-        }
+        Self { start: None }
     }
 }
 
@@ -61,19 +61,15 @@ impl Effect for EffectPride {
         "effect_pride"
     }
 
-    fn update<'a, 'b>(
-        &mut self,
-        matrix: &'b mut DeviceMatrixCustom<'a>,
-        _inputs: &[MatrixInput],
-    ) -> Result<(), Error> {
-        let time = std::time::Instant::now().duration_since(self.start);
+    fn update(&mut self, matrix: &mut Canvas, ctx: &EffectContext) -> Result<(), Error> {
+        let time = ctx.time.since(&mut self.start);
 
+        let width = matrix.width() as f32;
         matrix.iter_mut().for_each(|(x, _y, color)| {
-            let pos = (x as f32 / MATRIX_WIDTH as f32) * SCALE;
+            let pos = (x as f32 / width) * SCALE;
             *color = get_color(pos + time.as_secs_f32() * 0.02);
         });
 
-        matrix.send_update()?;
         Ok(())
     }
 }
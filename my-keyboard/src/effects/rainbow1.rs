@@ -1,18 +1,18 @@
+use std::time::Duration;
+
 use anyhow::Error;
-use openrazer::{Color, DeviceMatrixCustom};
+use openrazer::{Canvas, Color};
 
-use super::{Effect, MatrixInput};
+use super::{Effect, EffectContext};
 
 #[derive(Debug)]
 pub struct EffectRainbow1 {
-    start: std::time::Instant,
+    start: Option<Duration>,
 }
 
 impl EffectRainbow1 {
     pub fn new() -> Self {
-        Self {
-            start: std::time::Instant::now(),
-        }
+        Self { start: None }
     }
 }
 
@@ -21,19 +21,14 @@ impl Effect for EffectRainbow1 {
         "effect_rainbow_1"
     }
 
-    fn update<'a, 'b>(
-        &mut self,
-        matrix: &'b mut DeviceMatrixCustom<'a>,
-        _inputs: &[MatrixInput],
-    ) -> Result<(), Error> {
-        let time = std::time::Instant::now().duration_since(self.start);
+    fn update(&mut self, matrix: &mut Canvas, ctx: &EffectContext) -> Result<(), Error> {
+        let time = ctx.time.since(&mut self.start);
 
         let hue_rot = time.as_secs_f32() * 100.0;
         matrix.iter_mut().for_each(|(_x, _y, color)| {
             *color = Color::from_hsl(hue_rot, 1.0, 0.5);
         });
 
-        matrix.send_update()?;
         Ok(())
     }
 }
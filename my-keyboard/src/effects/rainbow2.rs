@@ -1,18 +1,18 @@
+use std::time::Duration;
+
 use anyhow::Error;
-use openrazer::{Color, DeviceMatrixCustom, MATRIX_WIDTH};
+use openrazer::{Canvas, Color};
 
-use super::{Effect, MatrixInput};
+use super::{Effect, EffectContext};
 
 #[derive(Debug)]
 pub struct EffectRainbow2 {
-    start: std::time::Instant,
+    start: Option<Duration>,
 }
 
 impl EffectRainbow2 {
     pub fn new() -> Self {
-        Self {
-            start: std::time::Instant::now(),
-        }
+        Self { start: None }
     }
 }
 
@@ -21,21 +21,17 @@ impl Effect for EffectRainbow2 {
         "effect_rainbow_2"
     }
 
-    fn update<'a, 'b>(
-        &mut self,
-        matrix: &'b mut DeviceMatrixCustom<'a>,
-        _inputs: &[MatrixInput],
-    ) -> Result<(), Error> {
-        let time = std::time::Instant::now().duration_since(self.start);
+    fn update(&mut self, matrix: &mut Canvas, ctx: &EffectContext) -> Result<(), Error> {
+        let time = ctx.time.since(&mut self.start);
 
         let hue_rot = time.as_secs_f32() * 100.0;
+        let width = matrix.width() as f32;
         matrix.iter_mut().for_each(|(x, y, color)| {
-            let hue = (x as f32 / MATRIX_WIDTH as f32) * 360.0;
+            let hue = (x as f32 / width) * 360.0;
             let hue = if y % 2 == 0 { hue } else { -hue };
             *color = Color::from_hsl(hue + hue_rot, 1.0, 0.5);
         });
 
-        matrix.send_update()?;
         Ok(())
     }
 }
@@ -1,7 +1,10 @@
+use std::time::Duration;
+
 use anyhow::Error;
-use openrazer::{Color, DeviceMatrixCustom, MATRIX_HEIGHT, MATRIX_WIDTH};
+use openrazer::{Canvas, Color};
+use rand::Rng;
 
-use super::{Effect, MatrixInput};
+use super::{Effect, EffectContext};
 
 #[derive(Debug)]
 struct Metaball {
@@ -13,36 +16,29 @@ struct Metaball {
 
 #[derive(Debug)]
 pub struct EffectRainbow3 {
-    start: std::time::Instant,
-    last_update: Option<std::time::Instant>,
+    start: Option<Duration>,
     balls: Vec<Metaball>,
 }
 
 impl EffectRainbow3 {
     pub fn new() -> Self {
         Self {
-            start: std::time::Instant::now(),
-            last_update: None,
-            balls: std::array::from_fn::<_, 3, _>(|_| Metaball {
-                x: rand::random_range((0.0)..=(MATRIX_WIDTH as f32 - 1.0)),
-                y: rand::random_range((0.0)..=(MATRIX_HEIGHT as f32 - 1.0)),
-                vx: rand::random_range((-4.0)..=4.0),
-                vy: rand::random_range((-4.0)..=4.0),
-            })
-            .into_iter()
-            .collect(),
+            start: None,
+            balls: Vec::new(),
         }
     }
 
-    fn update_balls(&mut self) {
-        let dt = if let Some(last_update) = self.last_update {
-            std::time::Instant::now()
-                .duration_since(last_update)
-                .as_secs_f32()
-        } else {
-            0.0
-        };
-        self.last_update = Some(std::time::Instant::now());
+    fn update_balls(&mut self, width: f32, height: f32, dt: f32, rng: &mut impl Rng) {
+        if self.balls.is_empty() {
+            self.balls = std::array::from_fn::<_, 3, _>(|_| Metaball {
+                x: rng.random_range((0.0)..=(width - 1.0).max(0.0)),
+                y: rng.random_range((0.0)..=(height - 1.0).max(0.0)),
+                vx: rng.random_range((-4.0)..=4.0),
+                vy: rng.random_range((-4.0)..=4.0),
+            })
+            .into_iter()
+            .collect();
+        }
 
         self.balls.iter_mut().for_each(|ball| {
             if ball.x < 0.0 {
@@ -53,12 +49,12 @@ impl EffectRainbow3 {
                 ball.y = 0.0;
                 ball.vy = f32::abs(ball.vy);
             }
-            if ball.x > MATRIX_WIDTH as f32 - 1.0 {
-                ball.x = MATRIX_WIDTH as f32 - 1.0;
+            if ball.x > width - 1.0 {
+                ball.x = width - 1.0;
                 ball.vx = -f32::abs(ball.vx);
             }
-            if ball.y > MATRIX_HEIGHT as f32 - 1.0 {
-                ball.y = MATRIX_HEIGHT as f32 - 1.0;
+            if ball.y > height - 1.0 {
+                ball.y = height - 1.0;
                 ball.vy = -f32::abs(ball.vy);
             }
             ball.x += ball.vx * dt;
@@ -72,14 +68,15 @@ impl Effect for EffectRainbow3 {
         "effect_rainbow_3"
     }
 
-    fn update<'a, 'b>(
-        &mut self,
-        matrix: &'b mut DeviceMatrixCustom<'a>,
-        _inputs: &[MatrixInput],
-    ) -> Result<(), Error> {
-        self.update_balls();
+    fn update(&mut self, matrix: &mut Canvas, ctx: &EffectContext) -> Result<(), Error> {
+        self.update_balls(
+            matrix.width() as f32,
+            matrix.height() as f32,
+            ctx.time.dt_secs(),
+            &mut *ctx.rng(),
+        );
 
-        let time = std::time::Instant::now().duration_since(self.start);
+        let time = ctx.time.since(&mut self.start);
 
         matrix.iter_mut().for_each(|(x, y, color)| {
             let x = x as f32;
@@ -92,7 +89,6 @@ impl Effect for EffectRainbow3 {
             *color = Color::from_hsl(dist_sum * 20.0 + time.as_secs_f32() * 10.0, 1.0, 0.5);
         });
 
-        matrix.send_update()?;
         Ok(())
     }
 }
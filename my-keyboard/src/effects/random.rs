@@ -1,49 +1,55 @@
 use anyhow::Error;
-use openrazer::{Color, DeviceMatrixCustom};
+use openrazer::{Canvas, Color};
+use rand::Rng;
 
-use super::{Effect, MatrixInput};
+use super::{Effect, EffectContext};
 
 #[derive(Debug)]
 pub struct EffectRandom {
-    initialized: bool,
+    colors: Option<Canvas>,
 }
 
 impl EffectRandom {
     pub fn new() -> Self {
-        Self { initialized: false }
+        Self { colors: None }
     }
 }
 
+fn random_color(rng: &mut impl Rng) -> Color {
+    Color::from_hsl(rng.random::<f32>() * 360.0, 1.0, 0.5)
+}
+
 impl Effect for EffectRandom {
     fn identifier(&self) -> &str {
         "effect_random"
     }
 
-    fn update<'a, 'b>(
-        &mut self,
-        matrix: &'b mut DeviceMatrixCustom<'a>,
-        inputs: &[MatrixInput],
-    ) -> Result<(), Error> {
-        let mut updated: bool = false;
-
-        if !self.initialized {
-            matrix.iter_mut().for_each(|(_x, _y, color)| {
-                *color = Color::from_hsl(rand::random::<f32>() * 360.0, 1.0, 0.5);
-            });
-            self.initialized = true;
-            updated = true;
-        }
+    fn is_static(&self) -> bool {
+        true
+    }
 
-        for input in inputs {
-            if let Some(color) = matrix.get_mut(input.x(), input.y()) {
-                *color = Color::from_hsl(rand::random::<f32>() * 360.0, 1.0, 0.5);
-                updated = true;
+    fn update(&mut self, matrix: &mut Canvas, ctx: &EffectContext) -> Result<(), Error> {
+        let mut rng = ctx.rng();
+        let colors = self.colors.get_or_insert_with(|| {
+            let mut colors = Canvas::new(matrix.width(), matrix.height());
+            colors
+                .iter_mut()
+                .for_each(|(_x, _y, color)| *color = random_color(&mut *rng));
+            colors
+        });
+
+        for input in ctx.inputs {
+            let new_color = random_color(&mut *rng);
+            for (x, y) in input.cells.iter() {
+                if let Some(color) = colors.get_mut(*x, *y) {
+                    *color = new_color;
+                }
             }
         }
 
-        if updated {
-            matrix.send_update()?;
-        }
+        matrix.iter_mut().for_each(|(x, y, color)| {
+            *color = colors.get(x, y).cloned().unwrap_or_default();
+        });
 
         Ok(())
     }
@@ -1,9 +1,12 @@
+use std::time::Duration;
+
 use anyhow::Error;
-use openrazer::{Color, DeviceMatrixCustom, MATRIX_HEIGHT, MATRIX_WIDTH};
+use openrazer::{Canvas, Color};
+use rand::Rng;
 
 use crate::util::simple_ease;
 
-use super::{Effect, MatrixInput};
+use super::{Coverage, Effect, EffectContext, MatrixInputKind};
 
 const RIPPLE_SPEED: f32 = 10.0;
 
@@ -12,26 +15,19 @@ struct Ripple {
     color: Color,
     x: f32,
     y: f32,
-    start: std::time::Instant,
+    start: Duration,
 }
 
 impl Ripple {
-    fn new(x: f32, y: f32) -> Self {
+    fn new(rng: &mut impl Rng, start: Duration, x: f32, y: f32) -> Self {
         Self {
-            color: Color::from_hsl(rand::random::<f32>() * 360.0, 1.0, 0.5),
+            color: Color::from_hsl(rng.random::<f32>() * 360.0, 1.0, 0.5),
             x,
             y,
-            start: std::time::Instant::now(),
+            start,
         }
     }
 
-    fn new_random() -> Self {
-        Self::new(
-            rand::random::<f32>() * MATRIX_WIDTH as f32,
-            rand::random::<f32>() * MATRIX_HEIGHT as f32,
-        )
-    }
-
     fn dist(&self, x: f32, y: f32) -> f32 {
         f32::sqrt((x - self.x).powi(2) + (y - self.y).powi(2))
     }
@@ -45,7 +41,7 @@ pub struct EffectRipple {
 impl EffectRipple {
     pub fn new() -> Self {
         Self {
-            ripples: vec![Ripple::new_random()],
+            ripples: Vec::new(),
         }
     }
 }
@@ -55,39 +51,42 @@ impl Effect for EffectRipple {
         "effect_ripple"
     }
 
-    fn update<'a, 'b>(
-        &mut self,
-        matrix: &'b mut DeviceMatrixCustom<'a>,
-        inputs: &[MatrixInput],
-    ) -> Result<(), Error> {
-        let now = std::time::Instant::now();
+    fn coverage(&self) -> Coverage {
+        Coverage::Brightness
+    }
+
+    fn update(&mut self, matrix: &mut Canvas, ctx: &EffectContext) -> Result<(), Error> {
+        let now = ctx.time.elapsed;
 
-        for input in inputs {
-            if let MatrixInput::Pressed { x, y } = input {
-                self.ripples.push(Ripple::new(*x as f32, *y as f32));
+        for input in ctx.inputs {
+            if input.kind == MatrixInputKind::Pressed
+                && let Some((x, y)) = ctx.mapper.center(&input.cells)
+            {
+                self.ripples.push(Ripple::new(&mut *ctx.rng(), now, x, y));
             }
-            // self.ripples
-            //     .push(Ripple::new(input.x() as f32, input.y() as f32));
+            // if let Some((x, y)) = ctx.mapper.center(&input.cells) {
+            //     self.ripples.push(Ripple::new(&mut *ctx.rng(), now, x, y));
+            // }
         }
 
         self.ripples.retain(|ripple| {
-            now.duration_since(ripple.start).as_secs_f32()
-                <= (MATRIX_WIDTH as f32 + MATRIX_HEIGHT as f32) / RIPPLE_SPEED
+            now.saturating_sub(ripple.start).as_secs_f32()
+                <= (matrix.width() as f32 + matrix.height() as f32) / RIPPLE_SPEED
         });
 
         if self.ripples.is_empty() {
-            self.ripples.push(Ripple::new_random());
+            let (x, y) = ctx.random_cell();
+            self.ripples.push(Ripple::new(&mut *ctx.rng(), now, x, y));
         }
 
         matrix.iter_mut().for_each(|(x, y, color)| {
-            let x = x as f32;
-            let y = y as f32;
+            let (x, y) = ctx.mapper.position(x, y);
 
             *color = self
                 .ripples
                 .iter()
                 .map(|ripple| {
-                    let t = now.duration_since(ripple.start);
+                    let t = now.saturating_sub(ripple.start);
                     let ripple_radius = t.as_secs_f32() * RIPPLE_SPEED;
                     let dist = ripple.dist(x, y) - ripple_radius;
                     let amount = 1.0 - simple_ease((dist.abs() / 2.0).clamp(0.0, 1.0));
@@ -96,8 +95,6 @@ impl Effect for EffectRipple {
                 .fold(Color::new(0.0, 0.0, 0.0), |acc, col| acc + col)
         });
 
-        matrix.send_update()?;
-
         Ok(())
     }
 }
@@ -0,0 +1,179 @@
+//! Golden frame tests for every built in effect.
+//!
+//! Each effect is run with a fixed clock, seed & key presses, and frames at set times are compared
+//! to `snapshots/<effect>.txt`. Run `UPDATE_SNAPSHOTS=1 cargo test` to write them after an
+//! intended change, & review the diff.
+
+use std::{path::PathBuf, time::Duration};
+
+use openrazer::{Canvas, Color, KeyCode, Layout, MatrixMapper};
+
+use crate::{
+    config::Config,
+    frame::FrameRenderer,
+    render::{PressScript, ScriptedPress},
+};
+
+const SEED: u64 = 1;
+const FPS: u32 = 15;
+/// Frames that are compared, each after a press so reactive effects have something lit.
+const CAPTURE_FRAMES: [u32; 5] = [4, 8, 12, 16, 24];
+/// Largest difference of a color component, out of 255.
+const TOLERANCE: u8 = 2;
+
+const PRESSES: [ScriptedPress; 4] = [
+    ScriptedPress {
+        time: 0.2,
+        key: KeyCode::KEY_A,
+        hold: 0.4,
+    },
+    ScriptedPress {
+        time: 0.5,
+        key: KeyCode::KEY_SPACE,
+        hold: 0.1,
+    },
+    ScriptedPress {
+        time: 1.0,
+        key: KeyCode::KEY_ENTER,
+        hold: 1.0,
+    },
+    ScriptedPress {
+        time: 1.0,
+        key: KeyCode::KEY_F5,
+        hold: 0.1,
+    },
+];
+
+fn snapshot_path(effect: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("snapshots")
+        .join(format!("{effect}.txt"))
+}
+
+/// One line per row of `#RRGGBB` cells, frames start with `frame <n>`.
+fn format_frames(frames: &[(u32, Canvas)]) -> String {
+    let mut str = String::new();
+    for (frame, canvas) in frames {
+        str += &format!("frame {frame}\n");
+        for y in 0..canvas.height() {
+            let row = (0..canvas.width())
+                .map(|x| canvas.get(x, y).cloned().unwrap_or_default().to_hex())
+                .collect::<Vec<_>>();
+            str += &row.join(" ");
+            str += "\n";
+        }
+    }
+    str
+}
+
+fn parse_frames(str: &str) -> Vec<(u32, Vec<Vec<Color>>)> {
+    let mut frames: Vec<(u32, Vec<Vec<Color>>)> = Vec::new();
+    for line in str.lines() {
+        if let Some(frame) = line.strip_prefix("frame ") {
+            frames.push((frame.parse().expect("Invalid frame number"), Vec::new()));
+        } else if let Some((_, rows)) = frames.last_mut() {
+            rows.push(
+                line.split(' ')
+                    .map(|hex| Color::from_hex(hex).expect("Invalid color"))
+                    .collect(),
+            );
+        }
+    }
+    frames
+}
+
+fn render(effect: &str, mapper: &MatrixMapper) -> Vec<(u32, Canvas)> {
+    let config = Config {
+        seed: Some(SEED),
+        ..Config::default()
+    };
+    let mut renderer = FrameRenderer::new(&config, mapper.clone());
+    assert!(renderer.cycler_mut().set_effect(effect));
+    let mut script = PressScript::new(&PRESSES);
+
+    let mut frames = Vec::new();
+    for frame in 0..=CAPTURE_FRAMES[CAPTURE_FRAMES.len() - 1] {
+        let inputs = script.inputs(frame as f32 / FPS as f32, renderer.mapper());
+        let time = renderer
+            .clock
+            .step(Duration::from_secs_f64(1.0 / FPS as f64));
+        let canvas = renderer.render(&inputs, time).unwrap();
+        if CAPTURE_FRAMES.contains(&frame) {
+            frames.push((frame, canvas.clone()));
+        }
+    }
+    frames
+}
+
+/// First difference larger than [`TOLERANCE`].
+fn compare(expected: &str, actual: &[(u32, Canvas)]) -> Result<(), String> {
+    let expected = parse_frames(expected);
+    if expected
+        .iter()
+        .map(|(frame, _)| *frame)
+        .ne(actual.iter().map(|(frame, _)| *frame))
+    {
+        return Err("Captured frames changed".to_owned());
+    }
+    for ((frame, rows), (_, canvas)) in expected.iter().zip(actual) {
+        if rows.len() != canvas.height() || rows.iter().any(|row| row.len() != canvas.width()) {
+            return Err(format!("Frame {frame} changed size"));
+        }
+        for (x, y, color) in canvas.iter() {
+            let expected = rows[y][x].to_quantized();
+            let actual = color.to_quantized();
+            if expected
+                .iter()
+                .zip(actual)
+                .any(|(a, b)| a.abs_diff(b) > TOLERANCE)
+            {
+                return Err(format!(
+                    "Frame {frame} at {x},{y} is {}, expected {}",
+                    color.to_hex(),
+                    rows[y][x].to_hex(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn effects() {
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let layout = Layout::builtins().next().unwrap();
+    let mapper = MatrixMapper::from_layout(&layout, None).unwrap();
+    let renderer = FrameRenderer::new(&Config::default(), mapper.clone());
+
+    let mut failures = Vec::new();
+    for effect in renderer.cycler().effect_identifiers() {
+        let frames = render(&effect, &mapper);
+        for (frame, canvas) in frames.iter() {
+            let black = Color::new(0.0, 0.0, 0.0).to_quantized();
+            if canvas
+                .iter()
+                .all(|(_, _, color)| color.to_quantized() == black)
+            {
+                failures.push(format!("{effect}: Frame {frame} is all black"));
+            }
+        }
+        let path = snapshot_path(&effect);
+        if update {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, format_frames(&frames)).unwrap();
+            continue;
+        }
+        let result = match std::fs::read_to_string(&path) {
+            Ok(expected) => compare(&expected, &frames),
+            Err(err) => Err(format!("Failed to read {path:?}: {err}")),
+        };
+        if let Err(err) = result {
+            failures.push(format!("{effect}: {err}"));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "Snapshots don't match, run with UPDATE_SNAPSHOTS=1 if this is intended:\n{}",
+        failures.join("\n")
+    );
+}
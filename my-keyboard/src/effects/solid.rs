@@ -0,0 +1,31 @@
+use anyhow::Error;
+use openrazer::{Canvas, Color};
+
+use super::{Effect, EffectContext};
+
+/// Single color, mostly useful as a masked layer.
+#[derive(Debug)]
+pub struct EffectSolid {
+    color: Color,
+}
+
+impl EffectSolid {
+    pub fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Effect for EffectSolid {
+    fn identifier(&self) -> &str {
+        "effect_solid"
+    }
+
+    fn is_static(&self) -> bool {
+        true
+    }
+
+    fn update(&mut self, matrix: &mut Canvas, _ctx: &EffectContext) -> Result<(), Error> {
+        matrix.fill(self.color);
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+//! Turns inputs into frames, shared by the daemon & `render` so both draw exactly the same.
+
+use std::{
+    cell::RefCell,
+    time::{Duration, Instant},
+};
+
+use anyhow::Error;
+use openrazer::{Canvas, Color, MatrixMapper, MATRIX_HEIGHT, MATRIX_WIDTH};
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{
+    clock::{FrameClock, FrameTime},
+    compositor::Layer,
+    config::Config,
+    cycler::EffectCycler,
+    effects::{self, EffectContext, EffectSolid},
+    input::{KeyStates, LockState, MatrixInput},
+};
+
+/// How many effects [`FrameRenderer::previous_effect`] can go back.
+const HISTORY_LENGTH: usize = 32;
+
+/// Color on some cells until a time, over everything else.
+#[derive(Debug)]
+struct TemporaryOverlay {
+    cells: Vec<(usize, usize)>,
+    color: Color,
+    until: Instant,
+}
+
+#[derive(Debug)]
+pub struct FrameRenderer {
+    cycler: EffectCycler,
+    /// Effects played before the current one, the most recent last.
+    history: Vec<String>,
+    overlays: Vec<TemporaryOverlay>,
+    mapper: MatrixMapper,
+    keys: KeyStates,
+    rng: RefCell<StdRng>,
+    canvas: Canvas,
+    pub locks: LockState,
+    pub clock: FrameClock,
+    /// Multiplies every color, `0.0` is off.
+    pub brightness: f32,
+}
+
+impl FrameRenderer {
+    /// Effects, layers & overlays from the config, no effect is playing yet.
+    pub fn new(config: &Config, mapper: MatrixMapper) -> Self {
+        let mut cycler = EffectCycler::new();
+        effects::add_effects_to_cycler(&mut cycler);
+        for layer_config in config.layers.iter() {
+            let Some(effect) = cycler.create_effect(&layer_config.effect) else {
+                log::warn!("Layer has unknown effect {:?}", layer_config.effect);
+                continue;
+            };
+            let mut layer = Layer::new(effect)
+                .with_blend(layer_config.blend)
+                .with_opacity(layer_config.opacity);
+            if let Some(group) = &layer_config.group {
+                let Some(cells) = mapper.group_cells(group) else {
+                    log::warn!("Layer has unknown group {group:?}");
+                    continue;
+                };
+                layer = layer.with_mask(cells);
+            }
+            cycler.add_layer(layer);
+        }
+        for overlay in config.overlays.iter() {
+            let Some(cells) = mapper.group_cells(&overlay.group) else {
+                log::warn!("Overlay has unknown group {:?}", overlay.group);
+                continue;
+            };
+            cycler
+                .add_layer(Layer::new(Box::new(EffectSolid::new(overlay.color))).with_mask(cells));
+        }
+        cycler.set_lock_color(config.lock_color);
+        cycler.set_transition(
+            config.transition,
+            Duration::from_secs_f32(config.transition_duration.max(0.0)),
+        );
+
+        let seed = config.seed.unwrap_or_else(rand::random);
+        log::info!("Using seed {seed}");
+
+        let mut clock = FrameClock::default();
+        clock.set_time_scale(config.time_scale);
+
+        Self {
+            cycler,
+            history: Vec::new(),
+            overlays: Vec::new(),
+            mapper,
+            keys: KeyStates::default(),
+            rng: RefCell::new(StdRng::seed_from_u64(seed)),
+            canvas: Canvas::new(MATRIX_WIDTH, MATRIX_HEIGHT),
+            locks: LockState::default(),
+            clock,
+            brightness: 1.0,
+        }
+    }
+
+    pub fn mapper(&self) -> &MatrixMapper {
+        &self.mapper
+    }
+
+    pub fn cycler(&self) -> &EffectCycler {
+        &self.cycler
+    }
+
+    pub fn cycler_mut(&mut self) -> &mut EffectCycler {
+        &mut self.cycler
+    }
+
+    /// If frames only change when there are inputs, e.g. when the effect is static, paused or off.
+    pub fn is_static(&self) -> bool {
+        self.clock.is_paused() || self.brightness <= 0.0 || self.cycler.is_static()
+    }
+
+    /// Forget held & recently released keys, so effects stop showing them.
+    pub fn release_keys(&mut self) {
+        self.keys = KeyStates::default();
+    }
+
+    fn push_history(&mut self, effect: Option<String>) {
+        let Some(effect) = effect else {
+            return;
+        };
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.remove(0);
+        }
+        self.history.push(effect);
+    }
+
+    /// Switch to a random effect, from the seeded RNG.
+    pub fn next_effect(&mut self) {
+        let current = self.cycler.current_effect_identifier().map(str::to_owned);
+        self.cycler.next_effect(&mut *self.rng.borrow_mut());
+        self.push_history(current);
+    }
+
+    /// Switch to an effect, remembering the current one for [`FrameRenderer::previous_effect`].
+    pub fn set_effect(&mut self, effect_identifier: &str) -> bool {
+        let current = self.cycler.current_effect_identifier().map(str::to_owned);
+        if !self.cycler.set_effect(effect_identifier) {
+            return false;
+        }
+        self.push_history(current);
+        true
+    }
+
+    /// Go back to the effect before the current one, returns if there was one.
+    pub fn previous_effect(&mut self) -> bool {
+        self.history
+            .pop()
+            .is_some_and(|effect| self.cycler.set_effect(&effect))
+    }
+
+    pub fn add_overlay(&mut self, cells: Vec<(usize, usize)>, color: Color, until: Instant) {
+        self.overlays.push(TemporaryOverlay {
+            cells,
+            color,
+            until,
+        });
+    }
+
+    /// Remove overlays that ran out.
+    pub fn prune_overlays(&mut self, now: Instant) {
+        self.overlays.retain(|overlay| overlay.until > now);
+    }
+
+    /// Draw the frame at `time`, see [`FrameClock::tick`] & [`FrameClock::step`].
+    pub fn render(&mut self, inputs: &[MatrixInput], time: FrameTime) -> Result<&Canvas, Error> {
+        inputs
+            .iter()
+            .for_each(|input| self.keys.update(input, time.elapsed));
+        self.keys.prune(time.elapsed);
+
+        self.cycler.render(
+            &mut self.canvas,
+            &EffectContext {
+                inputs,
+                keys: &self.keys,
+                locks: self.locks,
+                mapper: &self.mapper,
+                time,
+                rng: &self.rng,
+            },
+        )?;
+        for overlay in self.overlays.iter() {
+            for (x, y) in overlay.cells.iter() {
+                if let Some(color) = self.canvas.get_mut(*x, *y) {
+                    *color = overlay.color;
+                }
+            }
+        }
+        if self.brightness < 1.0 {
+            let brightness = self.brightness.max(0.0);
+            self.canvas
+                .iter_mut()
+                .for_each(|(_, _, color)| *color *= brightness);
+        }
+        Ok(&self.canvas)
+    }
+}
@@ -0,0 +1,227 @@
+//! Dimming, switching off or showing a screensaver after no input for a while.
+
+use std::time::{Duration, Instant};
+
+use openrazer::KeyCode;
+
+use crate::{
+    config::IdleStageConfig,
+    input::{MatrixInput, MatrixInputKind},
+};
+
+/// What the caller has to do after [`IdleTracker::process`] or [`IdleTracker::update`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdleChange {
+    /// A later stage started, switch to its effect if it has one.
+    Stage {
+        after: Duration,
+        effect: Option<String>,
+    },
+    /// There was input while idle, restore the effect from before the screensaver.
+    Wake,
+}
+
+#[derive(Debug)]
+pub struct IdleTracker {
+    /// Sorted by [`IdleStageConfig::after`].
+    stages: Vec<IdleStageConfig>,
+    fade: Duration,
+    last_input: Instant,
+    /// Index of the current stage, [`None`] while active.
+    stage: Option<usize>,
+    /// Brightness fades from `from` at `start` to `to`.
+    fade_from: f32,
+    fade_to: f32,
+    fade_start: Instant,
+    /// Key that woke us up, its events are dropped until it's released.
+    waking_key: Option<KeyCode>,
+}
+
+impl IdleTracker {
+    pub fn new(mut stages: Vec<IdleStageConfig>, fade: Duration, now: Instant) -> Self {
+        stages.sort_by(|a, b| a.after.total_cmp(&b.after));
+        Self {
+            stages,
+            fade,
+            last_input: now,
+            stage: None,
+            fade_from: 1.0,
+            fade_to: 1.0,
+            fade_start: now,
+            waking_key: None,
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.stage.is_some()
+    }
+
+    fn fade_to(&mut self, brightness: f32, now: Instant) {
+        self.fade_from = self.brightness(now);
+        self.fade_to = brightness;
+        self.fade_start = now;
+    }
+
+    /// Reset the idle time on input, & drop the key press that wakes us so it doesn't show in
+    /// reactive effects.
+    pub fn process(&mut self, inputs: &mut Vec<MatrixInput>, now: Instant) -> Option<IdleChange> {
+        if inputs.is_empty() {
+            return None;
+        }
+        let change = self.wake(now);
+        if change.is_some() {
+            self.waking_key = inputs
+                .iter()
+                .find(|input| input.kind == MatrixInputKind::Pressed)
+                .map(|input| input.key);
+        }
+        if let Some(key) = self.waking_key {
+            let released = inputs
+                .iter()
+                .any(|input| input.key == key && input.kind == MatrixInputKind::Released);
+            inputs.retain(|input| input.key != key);
+            if released {
+                self.waking_key = None;
+            }
+        }
+        change
+    }
+
+    /// Reset the idle time, e.g. on input or when the session is unlocked.
+    pub fn wake(&mut self, now: Instant) -> Option<IdleChange> {
+        self.last_input = now;
+        self.stage.take()?;
+        self.fade_to(1.0, now);
+        Some(IdleChange::Wake)
+    }
+
+    /// Move to the next stage once its time is up.
+    pub fn update(&mut self, now: Instant) -> Option<IdleChange> {
+        let idle_for = now.saturating_duration_since(self.last_input);
+        let next = self.stage.map_or(0, |stage| stage + 1);
+        let stage = self.stages.get(next)?;
+        // The config only allows finite seconds, a stage after `inf` never starts.
+        let after = Duration::try_from_secs_f32(stage.after.max(0.0)).unwrap_or(Duration::MAX);
+        if idle_for < after {
+            return None;
+        }
+        let (brightness, effect) = (stage.brightness, stage.effect.clone());
+        self.stage = Some(next);
+        self.fade_to(brightness.clamp(0.0, 1.0), now);
+        Some(IdleChange::Stage { after, effect })
+    }
+
+    /// Brightness multiplier of the current stage, fading between stages.
+    pub fn brightness(&self, now: Instant) -> f32 {
+        let progress = if self.fade.is_zero() {
+            1.0
+        } else {
+            (now.saturating_duration_since(self.fade_start).as_secs_f32() / self.fade.as_secs_f32())
+                .min(1.0)
+        };
+        self.fade_from + (self.fade_to - self.fade_from) * progress
+    }
+
+    pub fn is_fading(&self, now: Instant) -> bool {
+        self.fade_from != self.fade_to && now < self.fade_start + self.fade
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant, SystemTime};
+
+    use openrazer::KeyCode;
+
+    use super::{IdleChange, IdleTracker};
+    use crate::{
+        config::IdleStageConfig,
+        input::{MatrixInput, MatrixInputKind, Modifiers},
+    };
+
+    fn input(key: KeyCode, kind: MatrixInputKind) -> MatrixInput {
+        MatrixInput {
+            kind,
+            key,
+            time: SystemTime::now(),
+            modifiers: Modifiers::default(),
+            cells: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn stages() {
+        let start = Instant::now();
+        let secs = |secs: f32| start + Duration::from_secs_f32(secs);
+        let mut idle = IdleTracker::new(
+            vec![
+                IdleStageConfig {
+                    after: 20.0,
+                    brightness: 0.0,
+                    effect: None,
+                },
+                IdleStageConfig {
+                    after: 10.0,
+                    brightness: 0.5,
+                    effect: Some("effect_random".to_owned()),
+                },
+            ],
+            Duration::from_secs(1),
+            start,
+        );
+
+        assert_eq!(idle.update(secs(9.0)), None);
+        assert_eq!(
+            idle.update(secs(10.0)),
+            Some(IdleChange::Stage {
+                after: Duration::from_secs(10),
+                effect: Some("effect_random".to_owned())
+            })
+        );
+        assert_eq!(idle.brightness(secs(10.5)), 0.75);
+        assert!(idle.is_fading(secs(10.5)));
+        assert_eq!(idle.brightness(secs(12.0)), 0.5);
+        assert!(matches!(
+            idle.update(secs(20.0)),
+            Some(IdleChange::Stage { effect: None, .. })
+        ));
+        assert_eq!(idle.update(secs(100.0)), None);
+        assert_eq!(idle.brightness(secs(30.0)), 0.0);
+
+        // The key that wakes us is dropped until it's released, other keys aren't.
+        let mut inputs = vec![
+            input(KeyCode::KEY_A, MatrixInputKind::Pressed),
+            input(KeyCode::KEY_B, MatrixInputKind::Pressed),
+        ];
+        assert_eq!(
+            idle.process(&mut inputs, secs(30.0)),
+            Some(IdleChange::Wake)
+        );
+        assert_eq!(inputs.len(), 1);
+        assert_eq!(inputs[0].key, KeyCode::KEY_B);
+        assert!(!idle.is_idle());
+        assert_eq!(idle.brightness(secs(31.0)), 1.0);
+
+        let mut inputs = vec![input(KeyCode::KEY_A, MatrixInputKind::Released)];
+        assert_eq!(idle.process(&mut inputs, secs(31.0)), None);
+        assert!(inputs.is_empty());
+        let mut inputs = vec![input(KeyCode::KEY_A, MatrixInputKind::Pressed)];
+        idle.process(&mut inputs, secs(32.0));
+        assert_eq!(inputs.len(), 1);
+
+        // Idle time starts again from the last input.
+        assert_eq!(idle.update(secs(41.0)), None);
+        assert!(idle.update(secs(42.0)).is_some());
+
+        let mut idle = IdleTracker::new(
+            vec![IdleStageConfig {
+                after: f32::INFINITY,
+                brightness: 0.0,
+                effect: None,
+            }],
+            Duration::ZERO,
+            start,
+        );
+        assert_eq!(idle.update(secs(1e9)), None);
+    }
+}
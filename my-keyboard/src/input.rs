@@ -0,0 +1,366 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use openrazer::{EventSummary, InputEvent, KeyCode, LedCode, MatrixMapper};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatrixInputKind {
+    Pressed,
+    Released,
+    Repeat,
+}
+
+/// Set of held modifier keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    const KEYS: [KeyCode; 8] = [
+        KeyCode::KEY_LEFTSHIFT,
+        KeyCode::KEY_RIGHTSHIFT,
+        KeyCode::KEY_LEFTCTRL,
+        KeyCode::KEY_RIGHTCTRL,
+        KeyCode::KEY_LEFTALT,
+        KeyCode::KEY_RIGHTALT,
+        KeyCode::KEY_LEFTMETA,
+        KeyCode::KEY_RIGHTMETA,
+    ];
+
+    fn bit(key: KeyCode) -> Option<u8> {
+        Self::KEYS
+            .iter()
+            .position(|modifier| *modifier == key)
+            .map(|i| 1 << i)
+    }
+
+    /// Returns if the key is a modifier.
+    pub fn update(&mut self, key: KeyCode, held: bool) -> bool {
+        let Some(bit) = Self::bit(key) else {
+            return false;
+        };
+        if held {
+            self.0 |= bit;
+        } else {
+            self.0 &= !bit;
+        }
+        true
+    }
+
+    pub fn contains(&self, key: KeyCode) -> bool {
+        Self::bit(key).is_some_and(|bit| self.0 & bit != 0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn shift(&self) -> bool {
+        self.0 & 0b00000011 != 0
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.0 & 0b00001100 != 0
+    }
+
+    pub fn alt(&self) -> bool {
+        self.0 & 0b00110000 != 0
+    }
+
+    pub fn meta(&self) -> bool {
+        self.0 & 0b11000000 != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatrixInput {
+    pub kind: MatrixInputKind,
+    pub key: KeyCode,
+    /// Timestamp of the evdev event.
+    pub time: SystemTime,
+    /// Modifiers held after this input.
+    pub modifiers: Modifiers,
+    /// Cells on the matrix, empty if the key has no LED.
+    pub cells: Vec<(usize, usize)>,
+}
+
+/// Caps/Num/Scroll Lock state, from evdev LED events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LockState {
+    pub caps: bool,
+    pub num: bool,
+    pub scroll: bool,
+}
+
+impl LockState {
+    pub fn from_leds<T>(leds: T) -> Self
+    where
+        T: IntoIterator<Item = LedCode>,
+    {
+        let mut state = Self::default();
+        leds.into_iter().for_each(|led| state.set(led, true));
+        state
+    }
+
+    fn set(&mut self, led: LedCode, on: bool) {
+        match led {
+            LedCode::LED_CAPSL => self.caps = on,
+            LedCode::LED_NUML => self.num = on,
+            LedCode::LED_SCROLLL => self.scroll = on,
+            _ => {}
+        }
+    }
+
+    pub fn process(&mut self, event: InputEvent) {
+        if let EventSummary::Led(_, led, value) = event.destructure() {
+            self.set(led, value != 0);
+        }
+    }
+
+    /// Keys of the locks that are on.
+    pub fn keys(&self) -> impl Iterator<Item = KeyCode> {
+        [
+            (self.caps, KeyCode::KEY_CAPSLOCK),
+            (self.num, KeyCode::KEY_NUMLOCK),
+            (self.scroll, KeyCode::KEY_SCROLLLOCK),
+        ]
+        .into_iter()
+        .filter_map(|(on, key)| on.then_some(key))
+    }
+}
+
+/// Turns evdev events into [`MatrixInput`]s.
+#[derive(Debug, Default)]
+pub struct InputTracker {
+    modifiers: Modifiers,
+}
+
+impl InputTracker {
+    pub fn process(&mut self, event: InputEvent, mapper: &MatrixMapper) -> Option<MatrixInput> {
+        let EventSummary::Key(_, key, value) = event.destructure() else {
+            return None;
+        };
+        let kind = match value {
+            0 => MatrixInputKind::Released,
+            1 => MatrixInputKind::Pressed,
+            2 => MatrixInputKind::Repeat,
+            _ => return None,
+        };
+        self.modifiers
+            .update(key, kind != MatrixInputKind::Released);
+
+        let cells = mapper.cells(key).to_vec();
+        if cells.is_empty() && kind == MatrixInputKind::Pressed {
+            log::debug!("Unknown mapping: {key:?}");
+        }
+
+        Some(MatrixInput {
+            kind,
+            key,
+            time: event.timestamp(),
+            modifiers: self.modifiers,
+            cells,
+        })
+    }
+}
+
+/// How long released keys are remembered for.
+const RELEASED_KEY_RETAIN: Duration = Duration::from_secs(10);
+
+/// Times are from the frame clock, see [`crate::clock::FrameTime::elapsed`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyState {
+    pub pressed: Duration,
+    /// Number of repeat events since the key was pressed.
+    pub repeats: u32,
+    /// [`None`] while the key is held.
+    pub released: Option<Duration>,
+    pub cells: Vec<(usize, usize)>,
+}
+
+impl KeyState {
+    pub fn is_held(&self) -> bool {
+        self.released.is_none()
+    }
+
+    /// How long the key is or was held for.
+    pub fn held_for(&self, now: Duration) -> Duration {
+        self.released.unwrap_or(now).saturating_sub(self.pressed)
+    }
+
+    pub fn since_release(&self, now: Duration) -> Option<Duration> {
+        self.released.map(|released| now.saturating_sub(released))
+    }
+}
+
+/// Keys that are held or were released recently.
+#[derive(Debug, Default)]
+pub struct KeyStates {
+    keys: HashMap<KeyCode, KeyState>,
+}
+
+impl KeyStates {
+    pub fn update(&mut self, input: &MatrixInput, now: Duration) {
+        match input.kind {
+            MatrixInputKind::Pressed => {
+                self.keys.insert(
+                    input.key,
+                    KeyState {
+                        pressed: now,
+                        repeats: 0,
+                        released: None,
+                        cells: input.cells.clone(),
+                    },
+                );
+            }
+            MatrixInputKind::Repeat => {
+                if let Some(state) = self.keys.get_mut(&input.key) {
+                    state.repeats += 1;
+                }
+            }
+            MatrixInputKind::Released => {
+                if let Some(state) = self.keys.get_mut(&input.key) {
+                    state.released = Some(now);
+                }
+            }
+        }
+    }
+
+    /// Forget keys that were released a while ago.
+    pub fn prune(&mut self, now: Duration) {
+        self.keys.retain(|_, state| {
+            state
+                .since_release(now)
+                .is_none_or(|since| since <= RELEASED_KEY_RETAIN)
+        });
+    }
+
+    pub fn get(&self, key: KeyCode) -> Option<&KeyState> {
+        self.keys.get(&key)
+    }
+
+    pub fn is_held(&self, key: KeyCode) -> bool {
+        self.get(key).is_some_and(|state| state.is_held())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (KeyCode, &KeyState)> {
+        self.keys.iter().map(|(key, state)| (*key, state))
+    }
+
+    pub fn held(&self) -> impl Iterator<Item = (KeyCode, &KeyState)> {
+        self.iter().filter(|(_, state)| state.is_held())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use openrazer::{EventType, InputEvent, KeyCode, LedCode};
+
+    use super::{KeyStates, LockState, MatrixInput, MatrixInputKind, Modifiers};
+
+    fn input(key: KeyCode, kind: MatrixInputKind) -> MatrixInput {
+        MatrixInput {
+            kind,
+            key,
+            time: SystemTime::now(),
+            modifiers: Modifiers::default(),
+            cells: vec![(2, 3)],
+        }
+    }
+
+    #[test]
+    fn key_states() {
+        let secs = Duration::from_secs;
+        let mut keys = KeyStates::default();
+        keys.update(&input(KeyCode::KEY_A, MatrixInputKind::Pressed), secs(1));
+        keys.update(&input(KeyCode::KEY_A, MatrixInputKind::Repeat), secs(2));
+        keys.update(&input(KeyCode::KEY_A, MatrixInputKind::Repeat), secs(3));
+        // Repeats & releases of keys pressed before we started are ignored.
+        keys.update(&input(KeyCode::KEY_B, MatrixInputKind::Repeat), secs(3));
+        keys.update(&input(KeyCode::KEY_C, MatrixInputKind::Released), secs(3));
+        assert!(keys.get(KeyCode::KEY_B).is_none() && keys.get(KeyCode::KEY_C).is_none());
+
+        let state = keys.get(KeyCode::KEY_A).unwrap();
+        assert_eq!(state.repeats, 2);
+        assert_eq!(state.cells, [(2, 3)]);
+        assert_eq!(state.held_for(secs(4)), secs(3));
+        assert_eq!(state.since_release(secs(4)), None);
+        assert!(keys.is_held(KeyCode::KEY_A));
+
+        keys.update(&input(KeyCode::KEY_B, MatrixInputKind::Pressed), secs(4));
+        keys.update(&input(KeyCode::KEY_A, MatrixInputKind::Released), secs(5));
+        let state = keys.get(KeyCode::KEY_A).unwrap();
+        assert!(!state.is_held());
+        assert_eq!(state.held_for(secs(8)), secs(4));
+        assert_eq!(state.since_release(secs(8)), Some(secs(3)));
+        assert_eq!(
+            keys.held().map(|(key, _)| key).collect::<Vec<_>>(),
+            [KeyCode::KEY_B]
+        );
+        assert_eq!(keys.iter().count(), 2);
+
+        // Released keys are remembered for a while, held keys forever.
+        keys.prune(secs(15));
+        assert!(keys.get(KeyCode::KEY_A).is_some());
+        keys.prune(secs(16));
+        assert!(keys.get(KeyCode::KEY_A).is_none());
+        assert!(keys.is_held(KeyCode::KEY_B));
+
+        // Pressing again starts over.
+        keys.update(&input(KeyCode::KEY_B, MatrixInputKind::Repeat), secs(17));
+        keys.update(&input(KeyCode::KEY_B, MatrixInputKind::Released), secs(18));
+        keys.update(&input(KeyCode::KEY_B, MatrixInputKind::Pressed), secs(19));
+        let state = keys.get(KeyCode::KEY_B).unwrap();
+        assert_eq!(
+            (state.pressed, state.repeats, state.released),
+            (secs(19), 0, None)
+        );
+    }
+
+    #[test]
+    fn modifiers() {
+        let mut modifiers = Modifiers::default();
+        assert!(modifiers.update(KeyCode::KEY_LEFTSHIFT, true));
+        assert!(modifiers.update(KeyCode::KEY_RIGHTCTRL, true));
+        assert!(!modifiers.update(KeyCode::KEY_A, true));
+        assert!(modifiers.shift() && modifiers.ctrl() && !modifiers.alt() && !modifiers.meta());
+        assert!(modifiers.contains(KeyCode::KEY_RIGHTCTRL));
+        assert!(!modifiers.contains(KeyCode::KEY_LEFTCTRL));
+
+        modifiers.update(KeyCode::KEY_LEFTSHIFT, false);
+        modifiers.update(KeyCode::KEY_RIGHTCTRL, false);
+        assert!(modifiers.is_empty());
+    }
+
+    #[test]
+    fn locks() {
+        let mut locks = LockState::from_leds([LedCode::LED_NUML, LedCode::LED_MUTE]);
+        assert_eq!(locks.keys().collect::<Vec<_>>(), [KeyCode::KEY_NUMLOCK]);
+
+        let led = |led: LedCode, value| InputEvent::new(EventType::LED.0, led.0, value);
+        locks.process(led(LedCode::LED_CAPSL, 1));
+        locks.process(led(LedCode::LED_SCROLLL, 1));
+        locks.process(led(LedCode::LED_NUML, 0));
+        // Key events don't change locks, only the LEDs do.
+        locks.process(InputEvent::new(
+            EventType::KEY.0,
+            KeyCode::KEY_CAPSLOCK.code(),
+            1,
+        ));
+        assert_eq!(
+            locks,
+            LockState {
+                caps: true,
+                num: false,
+                scroll: true
+            }
+        );
+        assert_eq!(
+            locks.keys().collect::<Vec<_>>(),
+            [KeyCode::KEY_CAPSLOCK, KeyCode::KEY_SCROLLLOCK]
+        );
+    }
+}
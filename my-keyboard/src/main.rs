@@ -1,26 +1,148 @@
-use std::sync::{atomic::AtomicBool, Arc};
+use std::sync::{atomic::AtomicBool, Arc, Mutex};
 
 use anyhow::Error;
-use cycler::EffectCycler;
-use effects::MatrixInput;
-use openrazer::{query_razer_devices, EventSummary, KeyCode, MatrixMapper};
+use clap::Parser;
+use cli::{Args, Command};
+use config::Config;
+use control::ControlServer;
+use frame::FrameRenderer;
+use idle::{IdleChange, IdleTracker};
+use input::{InputTracker, LockState, MatrixInputKind};
+use metrics::Metrics;
+use openrazer::{
+    query_razer_devices, Canvas, Color, EvdevDeviceNonblocking, KeyCode, Layout, MatrixMapper,
+};
+use protocol::{Request, Response, Status};
+use scheduler::FrameScheduler;
+use session::SessionChange;
 
+mod cli;
+mod clock;
+mod compositor;
+mod config;
+mod control;
 pub mod cycler;
 mod effects;
+mod frame;
+mod idle;
+mod input;
+mod map_keys;
+mod metrics;
+mod protocol;
+mod render;
+mod scheduler;
+mod session;
+mod simulate;
+mod transition;
 pub mod util;
 
 const TARGET_FPS: u64 = 15;
 const TARGET_UPDATE_RATE: std::time::Duration = std::time::Duration::from_millis(1000 / TARGET_FPS);
 
+/// Longest sleep while the effect is static, so signals & control requests are noticed.
+const MAX_IDLE_SLEEP: std::time::Duration = std::time::Duration::from_millis(250);
+
 const EFFECT_CHANGE_TIME: std::time::Duration = std::time::Duration::from_secs(60 * 5);
 
 const FORCED_EFFECT: Option<&str> = None;
 
 const NEXT_EFFECT_KEY: Option<KeyCode> = Some(KeyCode::KEY_PAUSE);
 
+/// Pressed with Ctrl, pauses & resumes effects.
+const PAUSE_KEY: Option<KeyCode> = Some(KeyCode::KEY_PAUSE);
+
+/// Layout from the config, or the built in one for the product (any built in one if [`None`]).
+fn load_layout(config: &Config, id_product: Option<u16>) -> Result<Option<Layout>, Error> {
+    let layout = if let Some(path) = &config.layout {
+        log::info!("Using layout {path:?}");
+        Some(Layout::load(path)?)
+    } else if let Some(layout) = match id_product {
+        Some(id_product) => Layout::builtin(id_product),
+        None => Layout::builtins().next(),
+    } {
+        log::info!("Using built in layout {:?}", layout.name);
+        Some(layout)
+    } else {
+        log::warn!(
+            "No layout for product {:04X}, key feedback is disabled",
+            id_product.unwrap_or_default(),
+        );
+        None
+    };
+    Ok(layout)
+}
+
+fn load_matrix_mapper(config: &Config, layout: Option<&Layout>) -> Result<MatrixMapper, Error> {
+    let mut mapper = match layout {
+        Some(layout) => MatrixMapper::from_layout(layout, config.layout_variant.as_deref())?,
+        None => MatrixMapper::default(),
+    };
+    for (name, keys) in config.groups.iter() {
+        mapper.set_group(name, keys.iter().map(|key| key.0));
+    }
+    Ok(mapper)
+}
+
+/// Config file with the command line's overrides.
+fn load_config(args: &Args) -> Result<Config, Error> {
+    let mut config = Config::load_or_default(args.config.as_deref())?;
+    if args.layout.is_some() {
+        config.layout = args.layout.clone();
+    }
+    if args.layout_variant.is_some() {
+        config.layout_variant = args.layout_variant.clone();
+    }
+    if args.seed.is_some() {
+        config.seed = args.seed;
+    }
+    Ok(config)
+}
+
+/// Renderer from the config file again, keeping the effect, locks & pause of `renderer`.
+fn reload(
+    args: &Args,
+    id_product: u16,
+    renderer: &FrameRenderer,
+) -> Result<(Config, FrameRenderer), Error> {
+    let config = load_config(args)?;
+    let layout = load_layout(&config, Some(id_product))?;
+    let mut reloaded = FrameRenderer::new(&config, load_matrix_mapper(&config, layout.as_ref())?);
+    reloaded.locks = renderer.locks;
+    reloaded.clock.set_paused(renderer.clock.is_paused());
+    if let Some(effect) = renderer.cycler().current_effect_identifier() {
+        reloaded.cycler_mut().set_effect(effect);
+    }
+    Ok((config, reloaded))
+}
+
 pub fn main() -> Result<(), Error> {
     env_logger::init();
 
+    let mut args = Args::parse();
+    let config = load_config(&args)?;
+
+    match args.command.take().unwrap_or_default() {
+        Command::Run => run(&args, config),
+        Command::Render(args) => {
+            let layout = load_layout(&config, None)?;
+            render::render(&config, layout.as_ref(), &args)
+        }
+        Command::Simulate { attach, socket } => {
+            let layout = load_layout(&config, None)?;
+            if attach {
+                let socket = socket
+                    .or(config.control_socket.clone())
+                    .unwrap_or_else(protocol::default_socket_path);
+                simulate::attach(&config, layout.as_ref(), &socket)
+            } else {
+                simulate::simulate(&config, layout.as_ref())
+            }
+        }
+        Command::MapKeys { output, name } => map_keys::map_keys(&output, name),
+    }
+}
+
+fn run(args: &Args, mut config: Config) -> Result<(), Error> {
     let device = query_razer_devices()?
         .into_iter()
         .next()
@@ -30,219 +152,397 @@ pub fn main() -> Result<(), Error> {
         panic!("Razer device has no custom lighting.");
     };
 
-    let mut matrix_mapper = MatrixMapper::default();
-    // NOTE: This is the mapping for my keyboard (Razer Ornata Chroma)
-    matrix_mapper.add_mappings([
-        // Other
-        (KeyCode::KEY_ESC, (1, 0)),
-        // Function keys
-        (KeyCode::KEY_F1, (3, 0)),
-        (KeyCode::KEY_F2, (4, 0)),
-        (KeyCode::KEY_F3, (5, 0)),
-        (KeyCode::KEY_F4, (6, 0)),
-        (KeyCode::KEY_F5, (7, 0)),
-        (KeyCode::KEY_F6, (8, 0)),
-        (KeyCode::KEY_F7, (9, 0)),
-        (KeyCode::KEY_F8, (10, 0)),
-        (KeyCode::KEY_F9, (11, 0)),
-        (KeyCode::KEY_F10, (12, 0)),
-        (KeyCode::KEY_F11, (13, 0)),
-        (KeyCode::KEY_F12, (14, 0)),
-        // Control keys
-        (KeyCode::KEY_SYSRQ, (15, 0)),
-        (KeyCode::KEY_SCROLLLOCK, (16, 0)),
-        (KeyCode::KEY_PAUSE, (17, 0)),
-        (KeyCode::KEY_INSERT, (15, 1)),
-        (KeyCode::KEY_HOME, (16, 1)),
-        (KeyCode::KEY_PAGEUP, (17, 1)),
-        (KeyCode::KEY_DELETE, (15, 2)),
-        (KeyCode::KEY_END, (16, 2)),
-        (KeyCode::KEY_PAGEDOWN, (17, 2)),
-        // Arrow keys
-        (KeyCode::KEY_UP, (16, 4)),
-        (KeyCode::KEY_LEFT, (15, 5)),
-        (KeyCode::KEY_DOWN, (16, 5)),
-        (KeyCode::KEY_RIGHT, (17, 5)),
-        // Numpad
-        (KeyCode::KEY_NUMLOCK, (18, 1)),
-        (KeyCode::KEY_KPSLASH, (19, 1)),
-        (KeyCode::KEY_KPASTERISK, (20, 1)),
-        (KeyCode::KEY_KPMINUS, (21, 1)),
-        (KeyCode::KEY_KP7, (18, 2)),
-        (KeyCode::KEY_KP8, (19, 2)),
-        (KeyCode::KEY_KP9, (20, 2)),
-        (KeyCode::KEY_KPPLUS, (21, 2)),
-        (KeyCode::KEY_KP4, (18, 3)),
-        (KeyCode::KEY_KP5, (19, 3)),
-        (KeyCode::KEY_KP6, (20, 3)),
-        (KeyCode::KEY_KP1, (18, 4)),
-        (KeyCode::KEY_KP2, (19, 4)),
-        (KeyCode::KEY_KP3, (20, 4)),
-        (KeyCode::KEY_KPENTER, (21, 4)),
-        (KeyCode::KEY_KP0, (19, 5)),
-        (KeyCode::KEY_KPDOT, (20, 5)),
-        // Typewriter A
-        (KeyCode::KEY_GRAVE, (1, 1)),
-        (KeyCode::KEY_1, (2, 1)),
-        (KeyCode::KEY_2, (3, 1)),
-        (KeyCode::KEY_3, (4, 1)),
-        (KeyCode::KEY_4, (5, 1)),
-        (KeyCode::KEY_5, (6, 1)),
-        (KeyCode::KEY_6, (7, 1)),
-        (KeyCode::KEY_7, (8, 1)),
-        (KeyCode::KEY_8, (9, 1)),
-        (KeyCode::KEY_9, (10, 1)),
-        (KeyCode::KEY_0, (11, 1)),
-        (KeyCode::KEY_MINUS, (12, 1)),
-        (KeyCode::KEY_EQUAL, (13, 1)),
-        (KeyCode::KEY_BACKSPACE, (14, 1)),
-        // Typewriter B
-        (KeyCode::KEY_TAB, (1, 2)),
-        (KeyCode::KEY_Q, (2, 2)),
-        (KeyCode::KEY_W, (3, 2)),
-        (KeyCode::KEY_E, (4, 2)),
-        (KeyCode::KEY_R, (5, 2)),
-        (KeyCode::KEY_T, (6, 2)),
-        (KeyCode::KEY_Y, (7, 2)),
-        (KeyCode::KEY_U, (8, 2)),
-        (KeyCode::KEY_I, (9, 2)),
-        (KeyCode::KEY_O, (10, 2)),
-        (KeyCode::KEY_P, (11, 2)),
-        (KeyCode::KEY_LEFTBRACE, (12, 2)),
-        (KeyCode::KEY_RIGHTBRACE, (13, 2)),
-        (KeyCode::KEY_BACKSLASH, (14, 2)),
-        // Typewriter C
-        (KeyCode::KEY_CAPSLOCK, (1, 3)),
-        (KeyCode::KEY_A, (2, 3)),
-        (KeyCode::KEY_S, (3, 3)),
-        (KeyCode::KEY_D, (4, 3)),
-        (KeyCode::KEY_F, (5, 3)),
-        (KeyCode::KEY_G, (6, 3)),
-        (KeyCode::KEY_H, (7, 3)),
-        (KeyCode::KEY_J, (8, 3)),
-        (KeyCode::KEY_K, (9, 3)),
-        (KeyCode::KEY_L, (10, 3)),
-        (KeyCode::KEY_SEMICOLON, (11, 3)),
-        (KeyCode::KEY_APOSTROPHE, (12, 3)),
-        (KeyCode::KEY_ENTER, (14, 3)),
-        // Typewriter D
-        (KeyCode::KEY_LEFTSHIFT, (1, 4)),
-        (KeyCode::KEY_Z, (3, 4)),
-        (KeyCode::KEY_X, (4, 4)),
-        (KeyCode::KEY_C, (5, 4)),
-        (KeyCode::KEY_V, (6, 4)),
-        (KeyCode::KEY_B, (7, 4)),
-        (KeyCode::KEY_N, (8, 4)),
-        (KeyCode::KEY_M, (9, 4)),
-        (KeyCode::KEY_COMMA, (10, 4)),
-        (KeyCode::KEY_DOT, (11, 4)),
-        (KeyCode::KEY_SLASH, (12, 4)),
-        (KeyCode::KEY_RIGHTSHIFT, (14, 4)),
-        // Typewriter E
-        (KeyCode::KEY_LEFTCTRL, (1, 5)),
-        (KeyCode::KEY_LEFTMETA, (2, 5)),
-        (KeyCode::KEY_LEFTALT, (3, 5)),
-        (KeyCode::KEY_SPACE, (7, 5)),
-        (KeyCode::KEY_RIGHTALT, (11, 5)),
-        // NOTE: Not actually accessible with my keyboard, it seems to do some special stuff with
-        // it where it allows me to do something like fn+f6 to press the pause/play media button.
-        (KeyCode::KEY_FN, (12, 5)),
-        (KeyCode::KEY_COMPOSE, (13, 5)),
-        (KeyCode::KEY_RIGHTCTRL, (14, 5)),
-        // Some extra keys (Media keys that are emulated by my keyboard using fn+f*
-        (KeyCode::KEY_MUTE, (3, 0)),
-        (KeyCode::KEY_VOLUMEDOWN, (4, 0)),
-        (KeyCode::KEY_VOLUMEUP, (5, 0)),
-        (KeyCode::KEY_PREVIOUSSONG, (7, 0)),
-        (KeyCode::KEY_PLAYPAUSE, (8, 0)),
-        (KeyCode::KEY_NEXTSONG, (9, 0)),
-        (KeyCode(685), (11, 0)), // No clue what this key does. It toggles a light on my keyboard??
-        (KeyCode(684), (12, 0)), // Toggle game mode
-        (KeyCode(683), (13, 0)), // Keyboard lighting brightness down
-        (KeyCode(682), (14, 0)), // Keyboard lighting brightness up
-    ]);
+    let layout = load_layout(&config, Some(device.id_product))?;
+    let matrix_mapper = load_matrix_mapper(&config, layout.as_ref())?;
+    let mut renderer = FrameRenderer::new(&config, matrix_mapper);
 
     let mut evdev_device = device.get_evdev_device()?;
+    let mut led_device = device.get_led_evdev_device()?;
+    renderer.locks = led_device
+        .as_ref()
+        .map(|device| LockState::from_leds(device.led_state().iter()))
+        .unwrap_or_default();
+    let mut input_tracker = InputTracker::default();
 
-    let mut effect_cycler = EffectCycler::new(matrix_manager.effect_custom()?);
-    effects::add_effects_to_cycler(&mut effect_cycler);
+    let mut matrix = matrix_manager.effect_custom()?;
+
+    let metrics = Arc::new(Mutex::new(Metrics::new(
+        device.name.as_deref().unwrap_or("Unknown"),
+    )));
+    if let Some(address) = config.metrics_address {
+        metrics::serve_prometheus(address, Arc::clone(&metrics))?;
+    }
 
     if let Some(forced_effect) = FORCED_EFFECT {
-        if !effect_cycler.set_effect(forced_effect) {
+        if !renderer.cycler_mut().set_effect(forced_effect) {
             log::warn!("Invalid forced effect");
         } else {
             log::info!(
                 "Playing effect: {:?}",
-                effect_cycler.current_effect_identifier(),
+                renderer.cycler().current_effect_identifier(),
             );
         }
     }
 
+    let mut idle = IdleTracker::new(
+        config.idle.clone(),
+        std::time::Duration::from_secs_f32(config.idle_fade.max(0.0)),
+        std::time::Instant::now(),
+    );
+    // Effect from before an idle stage's screensaver.
+    let mut resume_effect = None;
+
+    let mut session = session::watch_logind(config.session.as_deref());
+    // Effect from before the locked effect.
+    let mut unlock_effect = None;
+
+    let mut brightness = config.brightness;
+    let control_socket = config
+        .control_socket
+        .clone()
+        .unwrap_or_else(protocol::default_socket_path);
+    let control = match ControlServer::bind(&control_socket) {
+        Ok(control) => Some(control),
+        Err(err) => {
+            log::warn!("Continuing without control socket: {err:#}");
+            None
+        }
+    };
+
     let mut cycle_next_effect_time = std::time::Instant::now();
+    // Last frame sent to the keyboard.
+    let mut last_sent: Option<Canvas> = None;
+    let mut scheduler = FrameScheduler::new(TARGET_UPDATE_RATE, std::time::Instant::now());
 
     let term = Arc::new(AtomicBool::new(false));
     let _ = signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&term));
     let _ = signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&term));
+    let dump_metrics = Arc::new(AtomicBool::new(false));
+    let _ = signal_hook::flag::register(signal_hook::consts::SIGUSR1, Arc::clone(&dump_metrics));
 
     loop {
-        let next_frame_time = std::time::Instant::now() + TARGET_UPDATE_RATE;
-
         if term.load(std::sync::atomic::Ordering::Relaxed) {
             break;
         }
 
-        if FORCED_EFFECT.is_none() && std::time::Instant::now() >= cycle_next_effect_time {
+        if dump_metrics.swap(false, std::sync::atomic::Ordering::Relaxed) {
+            log::info!("Metrics:\n{}", metrics.lock().unwrap().summary());
+        }
+
+        if FORCED_EFFECT.is_none()
+            && !renderer.clock.is_paused()
+            && !idle.is_idle()
+            && !session.as_ref().is_some_and(|session| session.is_locked())
+            && std::time::Instant::now() >= cycle_next_effect_time
+        {
             cycle_next_effect_time = std::time::Instant::now() + EFFECT_CHANGE_TIME;
 
-            effect_cycler.next_effect();
+            renderer.next_effect();
             log::info!(
                 "Playing effect: {:?}",
-                effect_cycler.current_effect_identifier(),
+                renderer.cycler().current_effect_identifier(),
             );
         }
 
-        let mut matrix_events = Vec::new();
+        let mut input_events = Vec::new();
+
+        if let Some(device) = evdev_device.as_mut() {
+            let result = loop {
+                match device.try_next() {
+                    Ok(Some(event)) => input_events.push(event),
+                    Ok(None) => break Ok(()),
+                    Err(err) => break Err(err),
+                }
+            };
+            if let Err(err) = result {
+                log::error!("Failed to read keyboard events, continuing without input: {err}");
+                evdev_device = None;
+            }
+        }
+
+        if let Some(device) = led_device.as_mut() {
+            let result = loop {
+                match device.try_next() {
+                    Ok(Some(event)) => renderer.locks.process(event),
+                    Ok(None) => break Ok(()),
+                    Err(err) => break Err(err),
+                }
+            };
+            if let Err(err) = result {
+                log::error!("Failed to read keyboard LED events, continuing without locks: {err}");
+                led_device = None;
+            }
+        }
+
+        let mut matrix_events = input_events
+            .into_iter()
+            .flat_map(|event| input_tracker.process(event, renderer.mapper()))
+            .collect::<Vec<_>>();
 
-        if let Some(evdev_device) = evdev_device.as_mut() {
-            while let Some(event) = evdev_device.try_next()? {
-                match event.destructure() {
-                    EventSummary::Key(_, key, 0) => {
-                        if let Some((x, y)) = matrix_mapper.map(key) {
-                            matrix_events.push(MatrixInput::Released { x, y });
+        let now = std::time::Instant::now();
+        let mut idle_changes = Vec::new();
+        for change in session
+            .as_mut()
+            .map(|session| session.poll())
+            .unwrap_or_default()
+        {
+            match change {
+                SessionChange::Locked => {
+                    log::info!("Session locked, hiding key presses");
+                    renderer.release_keys();
+                    if let Some(effect) = &config.locked_effect {
+                        let current = renderer.cycler().current_effect_identifier();
+                        if unlock_effect.is_none() {
+                            unlock_effect = resume_effect.take().or(current.map(str::to_owned));
+                        }
+                        if !renderer.cycler_mut().set_effect(effect) {
+                            log::warn!("Unknown locked effect {effect:?}");
                         }
                     }
-                    EventSummary::Key(_, key, 1) => {
-                        if NEXT_EFFECT_KEY == Some(key) {
-                            cycle_next_effect_time = std::time::Instant::now();
+                }
+                SessionChange::Unlocked => {
+                    log::info!("Session unlocked");
+                    if let Some(effect) = unlock_effect.take() {
+                        // A screensaver started while locked would resume the locked effect.
+                        resume_effect = None;
+                        renderer.cycler_mut().set_effect(&effect);
+                    }
+                    idle_changes.extend(idle.wake(now));
+                }
+                SessionChange::Sleeping => log::info!("Going to sleep, switching off"),
+                SessionChange::Resumed => {
+                    log::info!("Resumed from sleep");
+                    scheduler.reset(now);
+                    idle_changes.extend(idle.wake(now));
+                }
+            }
+        }
+        let (locked, sleeping) = session.as_ref().map_or((false, false), |session| {
+            (session.is_locked(), session.is_sleeping())
+        });
+        if locked {
+            matrix_events.clear();
+        }
+
+        idle_changes.extend(idle.process(&mut matrix_events, now));
+        idle_changes.extend(idle.update(now));
+        for change in idle_changes {
+            match change {
+                IdleChange::Stage { after, effect } => {
+                    log::info!("No input for {after:?}, entering idle stage");
+                    if let Some(effect) = effect {
+                        let current = renderer.cycler().current_effect_identifier();
+                        if resume_effect.is_none() {
+                            resume_effect = current.map(str::to_owned);
                         }
-                        if let Some((x, y)) = matrix_mapper.map(key) {
-                            matrix_events.push(MatrixInput::Pressed { x, y });
-                        } else {
-                            log::warn!("Unknown mapping: {key:?}");
+                        if !renderer.cycler_mut().set_effect(&effect) {
+                            log::warn!("Idle stage has unknown effect {effect:?}");
                         }
                     }
-                    EventSummary::Key(_, key, 2) => {
-                        if let Some((x, y)) = matrix_mapper.map(key) {
-                            matrix_events.push(MatrixInput::Repeat { x, y });
-                        }
+                }
+                IdleChange::Wake => {
+                    log::info!("Input, leaving idle");
+                    if let Some(effect) = resume_effect.take() {
+                        renderer.cycler_mut().set_effect(&effect);
+                    }
+                }
+            }
+        }
+        while let Some(pending) = control.as_ref().and_then(ControlServer::try_next) {
+            log::debug!("Control request {:?}", pending.request);
+            let error = |message: String| Response::Error { message };
+            let response = match &pending.request {
+                Request::NextEffect => {
+                    renderer.next_effect();
+                    cycle_next_effect_time = now + EFFECT_CHANGE_TIME;
+                    Response::Ok
+                }
+                Request::PreviousEffect => {
+                    if renderer.previous_effect() {
+                        cycle_next_effect_time = now + EFFECT_CHANGE_TIME;
+                        Response::Ok
+                    } else {
+                        error("No previous effect".to_owned())
+                    }
+                }
+                Request::SetEffect { effect } => {
+                    if renderer.set_effect(effect) {
+                        cycle_next_effect_time = now + EFFECT_CHANGE_TIME;
+                        Response::Ok
+                    } else {
+                        error(format!(
+                            "Unknown effect {effect:?}, expected one of: {}",
+                            renderer.cycler().effect_identifiers().join(", ")
+                        ))
+                    }
+                }
+                Request::Pause | Request::Resume => {
+                    renderer.clock.set_paused(pending.request == Request::Pause);
+                    Response::Ok
+                }
+                Request::SetBrightness { brightness: value } => {
+                    if (0.0..=1.0).contains(value) {
+                        brightness = *value;
+                        Response::Ok
+                    } else {
+                        error(format!("Brightness must be from 0 to 1, got {value}"))
                     }
-                    _ => {}
                 }
+                Request::ReloadConfig => match reload(args, device.id_product, &renderer) {
+                    Ok((reloaded_config, reloaded)) => {
+                        config = reloaded_config;
+                        renderer = reloaded;
+                        if let Some(effect) = resume_effect.take() {
+                            renderer.cycler_mut().set_effect(&effect);
+                        }
+                        idle = IdleTracker::new(
+                            config.idle.clone(),
+                            std::time::Duration::from_secs_f32(config.idle_fade.max(0.0)),
+                            now,
+                        );
+                        brightness = config.brightness;
+                        log::info!("Reloaded config");
+                        Response::Ok
+                    }
+                    Err(err) => error(format!("{err:#}")),
+                },
+                Request::Status => Response::Status(Status {
+                    effect: renderer
+                        .cycler()
+                        .current_effect_identifier()
+                        .map(str::to_owned),
+                    effects: renderer.cycler().effect_identifiers(),
+                    paused: renderer.clock.is_paused(),
+                    brightness,
+                    locked,
+                    idle: idle.is_idle(),
+                }),
+                Request::Overlay {
+                    group,
+                    color,
+                    seconds,
+                } => match (
+                    renderer.mapper().group_cells(group),
+                    Color::from_hex(color),
+                    std::time::Duration::try_from_secs_f32(*seconds),
+                ) {
+                    (Some(cells), Some(color), Ok(duration)) => {
+                        renderer.add_overlay(cells, color, now + duration);
+                        Response::Ok
+                    }
+                    (None, _, _) => error(format!("Unknown group {group:?}")),
+                    (_, None, _) => error(format!("Invalid color {color:?}")),
+                    (_, _, Err(_)) => error(format!("Invalid seconds {seconds}")),
+                },
+                Request::Metrics => Response::Metrics {
+                    summary: metrics.lock().unwrap().summary(),
+                },
+                // Answered by the control server itself.
+                Request::SubscribeFrames => error("Unexpected frame subscription".to_owned()),
+            };
+            pending.reply(response);
+        }
+        renderer.prune_overlays(now);
+
+        renderer.brightness = if sleeping {
+            0.0
+        } else {
+            idle.brightness(now) * brightness
+        };
+
+        for input in matrix_events
+            .iter()
+            .filter(|input| input.kind == MatrixInputKind::Pressed)
+        {
+            if Some(input.key) == PAUSE_KEY && input.modifiers.ctrl() {
+                let clock = &mut renderer.clock;
+                clock.set_paused(!clock.is_paused());
+                log::info!(
+                    "Effects {}",
+                    if clock.is_paused() {
+                        "paused"
+                    } else {
+                        "resumed"
+                    }
+                );
+            } else if Some(input.key) == NEXT_EFFECT_KEY {
+                cycle_next_effect_time = std::time::Instant::now();
             }
         }
 
-        effect_cycler.update(&matrix_events)?;
+        let render_start = std::time::Instant::now();
+        let time = renderer.clock.tick(render_start);
+        let canvas = renderer.render(&matrix_events, time)?;
+        let send_start = std::time::Instant::now();
+        // Static frames are drawn again each time we wake up, they're only sent if they changed.
+        let changed = last_sent.as_ref() != Some(canvas);
+        if changed {
+            matrix.send_canvas(canvas)?;
+            last_sent = Some(canvas.clone());
+        }
+        let sent = std::time::Instant::now();
+        if let Some(control) = &control {
+            control.publish_frame(canvas);
+        }
+
+        {
+            let mut metrics = metrics.lock().unwrap();
+            if changed {
+                metrics.record_frame(
+                    renderer
+                        .cycler()
+                        .current_effect_identifier()
+                        .unwrap_or("none"),
+                    send_start - render_start,
+                    sent - send_start,
+                );
+            }
+            let now = std::time::SystemTime::now();
+            for input in matrix_events
+                .iter()
+                .filter(|input| input.kind == MatrixInputKind::Pressed)
+            {
+                metrics.record_input_latency(now.duration_since(input.time).unwrap_or_default());
+            }
+        }
+
+        let skipped = scheduler.advance(sent);
+        if skipped > 0 {
+            metrics.lock().unwrap().record_dropped_frames(skipped);
+            log::warn!(
+                "Failed to update effect in time, skipped {skipped} frames (send SIGUSR1 for metrics)"
+            );
+        }
+
+        if !renderer.is_static() || idle.is_fading(std::time::Instant::now()) {
+            std::thread::sleep(
+                scheduler
+                    .deadline()
+                    .saturating_duration_since(std::time::Instant::now()),
+            );
+            continue;
+        }
 
-        let mut waited = false;
-        while std::time::Instant::now() < next_frame_time {
-            std::thread::sleep(std::time::Duration::from_nanos(100));
-            waited = true;
+        // Static frames only change with input, so sleep until there is some.
+        let mut deadline = std::time::Instant::now() + MAX_IDLE_SLEEP;
+        if FORCED_EFFECT.is_none() && !renderer.clock.is_paused() {
+            deadline = deadline.min(cycle_next_effect_time);
         }
-        if !waited {
-            log::warn!("Failed to update effect in time");
+        let timeout = deadline.saturating_duration_since(std::time::Instant::now());
+        let mut devices = evdev_device
+            .iter_mut()
+            .chain(led_device.iter_mut())
+            .collect::<Vec<_>>();
+        if devices.is_empty() {
+            std::thread::sleep(timeout);
+        } else if let Err(err) = EvdevDeviceNonblocking::wait_any(&mut devices, Some(timeout)) {
+            // Reading again reports which device failed.
+            log::debug!("Failed to wait for keyboard events: {err}");
         }
+        scheduler.reset(std::time::Instant::now());
     }
 
+    drop(matrix);
     log::info!("Exiting my-keyboard, setting keyboard matrix to spectrum");
     matrix_manager.effect_spectrum()?;
 
@@ -0,0 +1,362 @@
+//! Interactive wizard to build a layout file.
+
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::Path,
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+
+use anyhow::{Context, Error};
+use openrazer::{
+    query_razer_devices, Color, DeviceMatrixCustom, EvdevDeviceNonblocking, EventSummary, KeyCode,
+    Layout, LayoutCells, LayoutKey, MATRIX_HEIGHT, MATRIX_WIDTH,
+};
+
+const COLOR_CURRENT: Color = Color::new(1.0, 1.0, 1.0);
+const COLOR_MAPPED: Color = Color::new(0.0, 0.15, 0.0);
+const COLOR_NO_KEY: Color = Color::new(0.15, 0.0, 0.0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cell {
+    Unvisited,
+    Key(KeyCode),
+    /// Nothing lights up for the cell.
+    NoLed,
+    /// The cell lights up, but not under a key.
+    NoKey,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Key(KeyCode),
+    NoLed,
+    NoKey,
+    Back,
+    Finish,
+}
+
+/// Turns key events into actions.
+///
+/// Keys are actions when pressed, except for Ctrl which is used for commands: Ctrl+L for no LED,
+/// Ctrl+K for no key, Ctrl+B to go back & Ctrl+Q to finish early. Ctrl is mapped if it is
+/// released without being used for a command.
+#[derive(Debug, Default)]
+struct ActionParser {
+    ctrl: Option<KeyCode>,
+    ctrl_used: bool,
+}
+
+impl ActionParser {
+    fn process(&mut self, event: EventSummary) -> Option<Action> {
+        match event {
+            EventSummary::Key(_, key @ (KeyCode::KEY_LEFTCTRL | KeyCode::KEY_RIGHTCTRL), 1) => {
+                self.ctrl = Some(key);
+                self.ctrl_used = false;
+                None
+            }
+            EventSummary::Key(_, key, 0) if self.ctrl == Some(key) => {
+                self.ctrl = None;
+                (!self.ctrl_used).then_some(Action::Key(key))
+            }
+            EventSummary::Key(_, key, 1) if self.ctrl.is_some() => {
+                self.ctrl_used = true;
+                match key {
+                    KeyCode::KEY_L => Some(Action::NoLed),
+                    KeyCode::KEY_K => Some(Action::NoKey),
+                    KeyCode::KEY_B => Some(Action::Back),
+                    KeyCode::KEY_Q => Some(Action::Finish),
+                    _ => None,
+                }
+            }
+            EventSummary::Key(_, key, 1) => Some(Action::Key(key)),
+            _ => None,
+        }
+    }
+}
+
+/// Reads actions from the keyboard.
+#[derive(Debug)]
+struct ActionReader {
+    device: EvdevDeviceNonblocking,
+    parser: ActionParser,
+}
+
+impl ActionReader {
+    fn next(&mut self, term: &AtomicBool) -> Result<Option<Action>, Error> {
+        loop {
+            if term.load(std::sync::atomic::Ordering::Relaxed) {
+                return Ok(None);
+            }
+            self.device.wait(Some(Duration::from_millis(100)))?;
+            while let Some(event) = self.device.try_next()? {
+                if let Some(action) = self.parser.process(event.destructure()) {
+                    return Ok(Some(action));
+                }
+            }
+        }
+    }
+}
+
+/// What was found for each cell, visited in order.
+#[derive(Debug)]
+struct Wizard {
+    cells: Vec<Cell>,
+    current: usize,
+    /// Finished early, the remaining cells stay unvisited.
+    finished: bool,
+}
+
+impl Wizard {
+    fn new() -> Self {
+        Self {
+            cells: vec![Cell::Unvisited; MATRIX_WIDTH * MATRIX_HEIGHT],
+            current: 0,
+            finished: false,
+        }
+    }
+
+    /// If the wizard is over, with every cell visited or finished early.
+    fn is_done(&self) -> bool {
+        self.finished || self.current >= self.cells.len()
+    }
+
+    fn apply(&mut self, action: Action) {
+        let cell = match action {
+            Action::Key(key) => Cell::Key(key),
+            Action::NoLed => Cell::NoLed,
+            Action::NoKey => Cell::NoKey,
+            Action::Back => {
+                self.current = self.current.saturating_sub(1);
+                self.cells[self.current] = Cell::Unvisited;
+                return;
+            }
+            Action::Finish => {
+                self.finished = true;
+                return;
+            }
+        };
+        if let Some(current) = self.cells.get_mut(self.current) {
+            *current = cell;
+            self.current += 1;
+        }
+    }
+
+    /// Cells of each key, & notes about cells without an LED or a key & keys on multiple cells.
+    fn keys(&self) -> (BTreeMap<LayoutKey, LayoutCells>, Vec<String>) {
+        let mut keys: BTreeMap<LayoutKey, Vec<(usize, usize)>> = BTreeMap::new();
+        let mut no_led = Vec::new();
+        let mut no_key = Vec::new();
+        for (index, cell) in self.cells.iter().enumerate() {
+            let pos = (index % MATRIX_WIDTH, index / MATRIX_WIDTH);
+            match cell {
+                Cell::Key(key) => keys.entry(LayoutKey(*key)).or_default().push(pos),
+                Cell::NoLed => no_led.push(pos),
+                Cell::NoKey => no_key.push(pos),
+                Cell::Unvisited => {}
+            }
+        }
+
+        let mut notes = Vec::new();
+        if !no_led.is_empty() {
+            notes.push(format!("Cells with no LED: {no_led:?}"));
+        }
+        if !no_key.is_empty() {
+            notes.push(format!("LEDs with no key: {no_key:?}"));
+        }
+        for (key, cells) in keys.iter().filter(|(_, cells)| cells.len() > 1) {
+            notes.push(format!("{key} is on multiple cells: {cells:?}"));
+        }
+        let keys = keys
+            .into_iter()
+            .map(|(key, cells)| (key, LayoutCells(cells)))
+            .collect();
+        (keys, notes)
+    }
+}
+
+fn show(matrix: &mut DeviceMatrixCustom, cells: &[Cell], current: usize) -> Result<(), Error> {
+    matrix.iter_mut().for_each(|(x, y, color)| {
+        let index = x + y * MATRIX_WIDTH;
+        *color = match cells[index] {
+            _ if index == current => COLOR_CURRENT,
+            Cell::Key(_) => COLOR_MAPPED,
+            Cell::NoKey => COLOR_NO_KEY,
+            Cell::NoLed => Color::new(0.0, 0.0, 0.0),
+            Cell::Unvisited => Color::new(0.0, 0.0, 0.0),
+        };
+    });
+    matrix.send_update()?;
+    Ok(())
+}
+
+pub fn map_keys(output: &Path, name: Option<String>) -> Result<(), Error> {
+    let device = query_razer_devices()?
+        .into_iter()
+        .next()
+        .context("No Razer device found.")?;
+    let mut matrix_manager = device
+        .get_matrix_manager()?
+        .context("Razer device has no custom lighting.")?;
+    let mut reader = ActionReader {
+        device: device
+            .get_evdev_device()?
+            .context("Razer device has no keyboard input.")?,
+        parser: ActionParser::default(),
+    };
+
+    let term = Arc::new(AtomicBool::new(false));
+    let _ = signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&term));
+    let _ = signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&term));
+
+    println!("Press the key that is lit up.");
+    println!(
+        "Ctrl+L: Nothing lit up, Ctrl+K: Lit up but no key, Ctrl+B: Back, Ctrl+Q: Finish & save"
+    );
+    println!("Key presses also go to this terminal, so don't mind the mess.");
+
+    let mut wizard = Wizard::new();
+    let mut matrix = matrix_manager.effect_custom()?;
+
+    while !wizard.is_done() {
+        let current = wizard.current;
+        let (x, y) = (current % MATRIX_WIDTH, current / MATRIX_WIDTH);
+        show(&mut matrix, &wizard.cells, current)?;
+        print!(
+            "\r\x1B[K[{}/{}] Cell {x},{y}: ",
+            current + 1,
+            wizard.cells.len()
+        );
+        std::io::stdout().flush()?;
+
+        let Some(action) = reader.next(&term)? else {
+            break;
+        };
+        match action {
+            Action::Key(key) => println!("{}", LayoutKey(key)),
+            Action::NoLed => println!("No LED"),
+            Action::NoKey => println!("No key"),
+            Action::Back => println!("Back"),
+            Action::Finish => println!("Finish"),
+        }
+        wizard.apply(action);
+    }
+    drop(matrix);
+    matrix_manager.effect_spectrum()?;
+
+    if !wizard.is_done() {
+        println!("\nCancelled, nothing was written.");
+        return Ok(());
+    }
+
+    let (keys, notes) = wizard.keys();
+    let layout = Layout {
+        name: name
+            .or(device.name.clone())
+            .unwrap_or_else(|| format!("Razer {:04X}", device.id_product)),
+        products: vec![device.id_product],
+        keys,
+        ..Default::default()
+    };
+    layout.save(output)?;
+
+    // Notes go in the file as comments, JSON has none so they are only printed.
+    if output.extension().is_none_or(|ext| ext != "json") && !notes.is_empty() {
+        let content = std::fs::read_to_string(output)?;
+        let header = notes
+            .iter()
+            .map(|note| format!("# {note}\n"))
+            .collect::<String>();
+        std::fs::write(output, header + "\n" + &content)?;
+    }
+
+    println!("\nWrote layout to {output:?}");
+    notes.iter().for_each(|note| println!("{note}"));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use openrazer::{EventSummary, InputEvent, KeyCode, LayoutKey, MATRIX_WIDTH};
+
+    use super::{Action, ActionParser, Cell, Wizard};
+
+    fn key(key: KeyCode, value: i32) -> EventSummary {
+        InputEvent::new(openrazer::EventType::KEY.0, key.code(), value).destructure()
+    }
+
+    #[test]
+    fn actions() {
+        let mut parser = ActionParser::default();
+        assert_eq!(
+            parser.process(key(KeyCode::KEY_A, 1)),
+            Some(Action::Key(KeyCode::KEY_A))
+        );
+        assert_eq!(parser.process(key(KeyCode::KEY_A, 2)), None);
+        assert_eq!(parser.process(key(KeyCode::KEY_A, 0)), None);
+
+        // Ctrl is a command...
+        assert_eq!(parser.process(key(KeyCode::KEY_LEFTCTRL, 1)), None);
+        assert_eq!(parser.process(key(KeyCode::KEY_L, 1)), Some(Action::NoLed));
+        assert_eq!(parser.process(key(KeyCode::KEY_K, 1)), Some(Action::NoKey));
+        assert_eq!(parser.process(key(KeyCode::KEY_B, 1)), Some(Action::Back));
+        assert_eq!(parser.process(key(KeyCode::KEY_LEFTCTRL, 0)), None);
+
+        // ...or a key if it's released without one.
+        assert_eq!(parser.process(key(KeyCode::KEY_RIGHTCTRL, 1)), None);
+        assert_eq!(
+            parser.process(key(KeyCode::KEY_RIGHTCTRL, 0)),
+            Some(Action::Key(KeyCode::KEY_RIGHTCTRL))
+        );
+        assert_eq!(
+            parser.process(key(KeyCode::KEY_Q, 1)),
+            Some(Action::Key(KeyCode::KEY_Q))
+        );
+    }
+
+    #[test]
+    fn wizard() {
+        let mut wizard = Wizard::new();
+        wizard.apply(Action::Key(KeyCode::KEY_ESC));
+        wizard.apply(Action::NoLed);
+        wizard.apply(Action::Key(KeyCode::KEY_F2));
+        wizard.apply(Action::Back);
+        assert_eq!(wizard.current, 2);
+        assert_eq!(
+            wizard.cells[..3],
+            [Cell::Key(KeyCode::KEY_ESC), Cell::NoLed, Cell::Unvisited]
+        );
+
+        wizard.apply(Action::Key(KeyCode::KEY_F1));
+        wizard.apply(Action::NoKey);
+        wizard.apply(Action::Key(KeyCode::KEY_F1));
+        assert!(!wizard.is_done());
+        wizard.apply(Action::Finish);
+        assert!(wizard.is_done());
+
+        let (keys, notes) = wizard.keys();
+        assert_eq!(keys[&LayoutKey(KeyCode::KEY_ESC)].0, [(0, 0)]);
+        assert_eq!(keys[&LayoutKey(KeyCode::KEY_F1)].0, [(2, 0), (4, 0)]);
+        assert_eq!(
+            notes,
+            [
+                "Cells with no LED: [(1, 0)]",
+                "LEDs with no key: [(3, 0)]",
+                "KEY_F1 is on multiple cells: [(2, 0), (4, 0)]",
+            ]
+        );
+
+        // Back at the start stays there, actions after the last cell are ignored.
+        let mut wizard = Wizard::new();
+        wizard.apply(Action::Back);
+        assert_eq!(wizard.current, 0);
+        (0..wizard.cells.len()).for_each(|_| wizard.apply(Action::NoKey));
+        assert!(wizard.is_done());
+        wizard.apply(Action::Key(KeyCode::KEY_A));
+        assert_eq!(wizard.current, wizard.cells.len());
+        assert_eq!(wizard.keys().1.len(), 1);
+        assert_eq!(wizard.cells[MATRIX_WIDTH], Cell::NoKey);
+    }
+}
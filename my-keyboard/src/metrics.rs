@@ -0,0 +1,275 @@
+//! Frame timing statistics, logged on `SIGUSR1` & optionally served in Prometheus text format.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Error};
+
+/// Upper bounds of the histogram buckets in seconds, around the 66ms frame budget.
+const BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.002, 0.004, 0.008, 0.016, 0.033, 0.066, 0.133, 0.266, 0.5, 1.0,
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    /// Counts per bucket, the last one is everything above [`BUCKETS`].
+    counts: [u64; BUCKETS.len() + 1],
+    sum: Duration,
+    max: Duration,
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: Duration) {
+        let secs = value.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.max = self.max.max(value);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::ZERO,
+            count => self.sum.div_f64(count as f64),
+        }
+    }
+
+    /// Upper bound of the bucket the quantile is in, the max if it's above every bucket.
+    pub fn quantile(&self, q: f64) -> Duration {
+        let rank = (q.clamp(0.0, 1.0) * self.count() as f64).ceil() as u64;
+        let mut seen = 0;
+        for (count, bound) in self.counts.iter().zip(BUCKETS) {
+            seen += count;
+            if seen >= rank.max(1) {
+                return Duration::from_secs_f64(bound).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "n={} mean={:.2?} p50={:.2?} p99={:.2?} max={:.2?}",
+            self.count(),
+            self.mean(),
+            self.quantile(0.5),
+            self.quantile(0.99),
+            self.max,
+        )
+    }
+
+    /// `_bucket`, `_sum` & `_count` lines, `labels` are without braces, e.g. `effect="a"`.
+    fn write_prometheus(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (count, bound) in self.counts.iter().zip(BUCKETS) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels}{sep}le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{labels}{sep}le=\"+Inf\"}} {}",
+            self.count()
+        );
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{labels}}}")
+        };
+        let _ = writeln!(out, "{name}_sum{labels} {}", self.sum.as_secs_f64());
+        let _ = writeln!(out, "{name}_count{labels} {}", self.count());
+    }
+}
+
+/// Measurements of the main loop since startup.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    /// Name of the device frames are sent to.
+    device: String,
+    frames: u64,
+    dropped_frames: u64,
+    /// Render time by effect identifier.
+    render: BTreeMap<String, Histogram>,
+    /// How long `send_update` took.
+    send: Histogram,
+    /// From the evdev event of a key press to its frame being sent.
+    input_latency: Histogram,
+}
+
+impl Metrics {
+    pub fn new(device: &str) -> Self {
+        Self {
+            device: device.to_owned(),
+            ..Self::default()
+        }
+    }
+
+    pub fn record_frame(&mut self, effect: &str, render: Duration, send: Duration) {
+        self.frames += 1;
+        self.render
+            .entry(effect.to_owned())
+            .or_default()
+            .record(render);
+        self.send.record(send);
+    }
+
+    pub fn record_dropped_frames(&mut self, count: u32) {
+        self.dropped_frames += count as u64;
+    }
+
+    pub fn record_input_latency(&mut self, latency: Duration) {
+        self.input_latency.record(latency);
+    }
+
+    /// Human readable, one line per histogram.
+    pub fn summary(&self) -> String {
+        let mut out = format!(
+            "Frames: {}, dropped: {}\n",
+            self.frames, self.dropped_frames
+        );
+        for (effect, histogram) in self.render.iter() {
+            let _ = writeln!(out, "Render {effect}: {}", histogram.summary());
+        }
+        let _ = writeln!(out, "Send to {}: {}", self.device, self.send.summary());
+        let _ = write!(out, "Input to light: {}", self.input_latency.summary());
+        out
+    }
+
+    /// [Prometheus text format](https://prometheus.io/docs/instrumenting/exposition_formats/).
+    pub fn prometheus(&self) -> String {
+        let device = format!("device=\"{}\"", escape_label(&self.device));
+        let mut out = String::new();
+        out += "# HELP my_keyboard_frames_total Frames sent to the keyboard.\n";
+        out += "# TYPE my_keyboard_frames_total counter\n";
+        let _ = writeln!(out, "my_keyboard_frames_total{{{device}}} {}", self.frames);
+        out += "# HELP my_keyboard_dropped_frames_total Frames skipped for being late.\n";
+        out += "# TYPE my_keyboard_dropped_frames_total counter\n";
+        let _ = writeln!(
+            out,
+            "my_keyboard_dropped_frames_total{{{device}}} {}",
+            self.dropped_frames
+        );
+        out += "# HELP my_keyboard_render_seconds Time to render a frame, by effect.\n";
+        out += "# TYPE my_keyboard_render_seconds histogram\n";
+        for (effect, histogram) in self.render.iter() {
+            let labels = format!("effect=\"{}\"", escape_label(effect));
+            histogram.write_prometheus(&mut out, "my_keyboard_render_seconds", &labels);
+        }
+        out += "# HELP my_keyboard_send_seconds Time to write a frame to the device.\n";
+        out += "# TYPE my_keyboard_send_seconds histogram\n";
+        self.send
+            .write_prometheus(&mut out, "my_keyboard_send_seconds", &device);
+        out +=
+            "# HELP my_keyboard_input_latency_seconds From a key press to its frame being sent.\n";
+        out += "# TYPE my_keyboard_input_latency_seconds histogram\n";
+        self.input_latency
+            .write_prometheus(&mut out, "my_keyboard_input_latency_seconds", &device);
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serve [`Metrics::prometheus`] over HTTP on a background thread.
+pub fn serve_prometheus(address: SocketAddr, metrics: Arc<Mutex<Metrics>>) -> Result<(), Error> {
+    if !address.ip().is_loopback() {
+        log::warn!("Metrics are served on {address}, which isn't a loopback address");
+    }
+    let listener = TcpListener::bind(address)
+        .with_context(|| format!("Failed to listen for metrics on {address}"))?;
+    log::info!("Serving metrics on http://{address}/metrics");
+    std::thread::Builder::new()
+        .name("metrics".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| respond(stream, &metrics));
+                if let Err(err) = result {
+                    log::debug!("Failed to serve metrics: {err}");
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, metrics: &Mutex<Metrics>) -> Result<(), std::io::Error> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    // Every path gets the metrics, only the headers need to be read.
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && line != "\r\n" && line != "\n" {
+        line.clear();
+    }
+    let body = metrics.lock().unwrap().prometheus();
+    write!(
+        stream,
+        "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{Histogram, Metrics};
+
+    #[test]
+    fn histogram() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.quantile(0.5), Duration::ZERO);
+        for ms in [1, 1, 3, 50] {
+            histogram.record(Duration::from_millis(ms));
+        }
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.mean(), Duration::from_micros(13750));
+        assert_eq!(histogram.quantile(0.5), Duration::from_millis(1));
+        assert_eq!(histogram.quantile(0.75), Duration::from_millis(4));
+        // Clamped to the largest value instead of the bucket's 66ms.
+        assert_eq!(histogram.quantile(1.0), Duration::from_millis(50));
+
+        histogram.record(Duration::from_secs(3));
+        assert_eq!(histogram.quantile(1.0), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn prometheus() {
+        let mut metrics = Metrics::new("Razer \"Keyboard\"");
+        metrics.record_frame("effect_ripple", Duration::from_millis(3), Duration::ZERO);
+        metrics.record_dropped_frames(2);
+        let out = metrics.prometheus();
+        assert!(out.contains("my_keyboard_frames_total{device=\"Razer \\\"Keyboard\\\"\"} 1\n"));
+        assert!(
+            out.contains("my_keyboard_dropped_frames_total{device=\"Razer \\\"Keyboard\\\"\"} 2\n")
+        );
+        assert!(out.contains(
+            "my_keyboard_render_seconds_bucket{effect=\"effect_ripple\",le=\"0.002\"} 0\n"
+        ));
+        assert!(out.contains(
+            "my_keyboard_render_seconds_bucket{effect=\"effect_ripple\",le=\"0.004\"} 1\n"
+        ));
+        assert!(out.contains("my_keyboard_render_seconds_count{effect=\"effect_ripple\"} 1\n"));
+        assert!(out.contains("my_keyboard_input_latency_seconds_bucket{device=\"Razer \\\"Keyboard\\\"\",le=\"+Inf\"} 0\n"));
+    }
+}
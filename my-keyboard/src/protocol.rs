@@ -0,0 +1,86 @@
+//! Control socket protocol, shared with `my-keyboardctl` through `#[path]`.
+//!
+//! Each request is a line of JSON, e.g. `{"command":"set_effect","effect":"effect_ripple"}`, &
+//! is answered with a line of JSON, e.g. `{"result":"ok"}`. After `subscribe_frames` the client
+//! only receives frames, e.g. for `my-keyboard simulate --attach`.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Request {
+    NextEffect,
+    PreviousEffect,
+    SetEffect {
+        effect: String,
+    },
+    Pause,
+    Resume,
+    /// `0.0` is off & `1.0` is full brightness.
+    SetBrightness {
+        brightness: f32,
+    },
+    /// Load the config file again, the metrics address & control socket stay the same.
+    ReloadConfig,
+    Status,
+    /// Hex color on a group for some seconds, over everything else.
+    Overlay {
+        group: String,
+        color: String,
+        seconds: f32,
+    },
+    /// Frame timing summary.
+    Metrics,
+    /// Answered with `ok`, then every frame sent to the keyboard until the client disconnects.
+    SubscribeFrames,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Response {
+    Ok,
+    Status(Status),
+    Metrics {
+        summary: String,
+    },
+    /// Colors of the cells in row order, as `RRGGBB` hex for each cell.
+    Frame {
+        width: usize,
+        height: usize,
+        colors: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    /// Identifier of the current effect.
+    pub effect: Option<String>,
+    /// Every effect that can be set.
+    pub effects: Vec<String>,
+    pub paused: bool,
+    pub brightness: f32,
+    /// If the session is locked & key presses are hidden.
+    pub locked: bool,
+    /// If an idle stage is active.
+    pub idle: bool,
+}
+
+/// `$XDG_RUNTIME_DIR/my-keyboard/control.sock`, or in `/tmp/my-keyboard-<uid>/` without it.
+pub fn default_socket_path() -> PathBuf {
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("my-keyboard"),
+        None => std::env::temp_dir().join(format!("my-keyboard-{}", euid())),
+    };
+    dir.join("control.sock")
+}
+
+/// Effective user ID of the process.
+pub fn euid() -> u32 {
+    // SAFETY: geteuid has no preconditions & can't fail.
+    unsafe { libc::geteuid() }
+}
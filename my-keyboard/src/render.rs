@@ -0,0 +1,325 @@
+//! Headless rendering of effects to images, drawn with the layout's key outlines.
+
+use std::{fs::File, io::BufWriter, str::FromStr, time::Duration};
+
+use anyhow::{bail, Context, Error};
+use openrazer::{
+    Canvas, Color, KeyCode, KeyEvent, KeyRect, Layout, LayoutKey, MatrixMapper, KEY_UNIT_MM,
+    MATRIX_HEIGHT, MATRIX_WIDTH,
+};
+
+use crate::{
+    cli::RenderArgs,
+    config::Config,
+    frame::FrameRenderer,
+    input::{InputTracker, MatrixInput},
+};
+
+/// Space around the keyboard in millimetres.
+const MARGIN_MM: f64 = 5.0;
+/// Space between keys in millimetres.
+const KEY_GAP_MM: f64 = 1.0;
+const BACKGROUND: Color = Color::new(0.08, 0.08, 0.08);
+
+/// How long scripted presses are held for if not given.
+const DEFAULT_HOLD: f32 = 0.1;
+
+/// Key press at a time, `SECONDS:KEY[:HOLD_SECONDS]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScriptedPress {
+    pub time: f32,
+    pub key: KeyCode,
+    pub hold: f32,
+}
+
+impl FromStr for ScriptedPress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_secs = |s: &str| {
+            s.parse::<f32>()
+                .ok()
+                .filter(|secs| *secs >= 0.0 && secs.is_finite())
+                .ok_or_else(|| format!("Invalid seconds {s:?}"))
+        };
+        let (time, key, hold) = match s.split(':').collect::<Vec<_>>().as_slice() {
+            [time, key] => (parse_secs(time)?, *key, DEFAULT_HOLD),
+            [time, key, hold] => (parse_secs(time)?, *key, parse_secs(hold)?),
+            _ => return Err(format!("Expected SECONDS:KEY[:HOLD_SECONDS], got {s:?}")),
+        };
+        Ok(Self {
+            time,
+            key: LayoutKey::from_str(key)?.0,
+            hold,
+        })
+    }
+}
+
+/// Replays [`ScriptedPress`]es as inputs.
+#[derive(Debug)]
+pub struct PressScript {
+    /// Key events by time, the next one last.
+    events: Vec<(f32, KeyEvent)>,
+    input_tracker: InputTracker,
+}
+
+impl PressScript {
+    pub fn new(presses: &[ScriptedPress]) -> Self {
+        let mut events = presses
+            .iter()
+            .flat_map(|press| {
+                [
+                    (press.time, KeyEvent::new(press.key, 1)),
+                    (press.time + press.hold, KeyEvent::new(press.key, 0)),
+                ]
+            })
+            .collect::<Vec<_>>();
+        events.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        events.reverse();
+        Self {
+            events,
+            input_tracker: InputTracker::default(),
+        }
+    }
+
+    /// Inputs up to & including `time` in seconds, that weren't returned yet.
+    pub fn inputs(&mut self, time: f32, mapper: &MatrixMapper) -> Vec<MatrixInput> {
+        let mut inputs = Vec::new();
+        while let Some((_, event)) = self.events.pop_if(|(t, _)| *t <= time) {
+            inputs.extend(self.input_tracker.process(*event, mapper));
+        }
+        inputs
+    }
+}
+
+/// Outline of a matrix cell in millimetres.
+pub type CellRect = ((usize, usize), KeyRect);
+
+/// Outline of every cell in millimetres, keys with multiple LEDs are split between their cells.
+///
+/// Cells without geometry from any of their keys are one key unit on the grid, and without a
+/// layout every cell is.
+pub fn cell_rects(layout: Option<&Layout>, variant: Option<&str>) -> Result<Vec<CellRect>, Error> {
+    let grid_rect = |(x, y): (usize, usize)| KeyRect {
+        x: x as f64 * KEY_UNIT_MM,
+        y: y as f64 * KEY_UNIT_MM,
+        w: KEY_UNIT_MM,
+        h: KEY_UNIT_MM,
+    };
+    let Some(layout) = layout else {
+        return Ok((0..MATRIX_HEIGHT)
+            .flat_map(|y| (0..MATRIX_WIDTH).map(move |x| (x, y)))
+            .map(|cell| (cell, grid_rect(cell)))
+            .collect());
+    };
+
+    let keys = layout.keys(variant)?;
+    let mut rects = Vec::new();
+    for (key, rect) in layout.geometry(variant)? {
+        let Some(cells) = keys.get(&key) else {
+            continue;
+        };
+        let mut cells = cells.0.clone();
+        cells.sort();
+        let parts = rect.split(cells.len()).collect::<Vec<_>>();
+        rects.extend(cells.into_iter().zip(parts));
+    }
+    let mut missing = keys
+        .values()
+        .flat_map(|cells| cells.0.iter().cloned())
+        .filter(|cell| !rects.iter().any(|(c, _)| c == cell))
+        .collect::<Vec<_>>();
+    missing.sort();
+    missing.dedup();
+    rects.extend(missing.into_iter().map(|cell| (cell, grid_rect(cell))));
+    Ok(rects)
+}
+
+/// Draws frames as RGB images.
+#[derive(Debug)]
+pub struct KeyImage {
+    rects: Vec<CellRect>,
+    /// Pixels per millimetre.
+    scale: f64,
+    origin: (f64, f64),
+    pub width: usize,
+    pub height: usize,
+}
+
+impl KeyImage {
+    /// Top left & size of the image in millimetres.
+    fn area(rects: &[CellRect]) -> ((f64, f64), (f64, f64)) {
+        let (min, max) = rects.iter().fold(
+            ((f64::MAX, f64::MAX), (f64::MIN, f64::MIN)),
+            |(min, max), (_, rect)| {
+                (
+                    (min.0.min(rect.x), min.1.min(rect.y)),
+                    (max.0.max(rect.x + rect.w), max.1.max(rect.y + rect.h)),
+                )
+            },
+        );
+        (
+            (min.0 - MARGIN_MM, min.1 - MARGIN_MM),
+            (
+                max.0 - min.0 + MARGIN_MM * 2.0,
+                max.1 - min.1 + MARGIN_MM * 2.0,
+            ),
+        )
+    }
+
+    pub fn new(rects: Vec<CellRect>, scale: f64) -> Self {
+        let (origin, (width, height)) = Self::area(&rects);
+        Self {
+            width: (width * scale).ceil() as usize,
+            height: (height * scale).ceil() as usize,
+            rects,
+            scale,
+            origin,
+        }
+    }
+
+    /// Largest image that fits in `width` x `height` pixels.
+    pub fn fit(rects: Vec<CellRect>, width: usize, height: usize) -> Self {
+        let (_, (mm_width, mm_height)) = Self::area(&rects);
+        let scale = f64::min(width as f64 / mm_width, height as f64 / mm_height);
+        let mut image = Self::new(rects, scale);
+        image.width = image.width.min(width);
+        image.height = image.height.min(height);
+        image
+    }
+
+    pub fn draw(&self, canvas: &Canvas) -> Vec<u8> {
+        let mut pixels = BACKGROUND.to_quantized().repeat(self.width * self.height);
+        // At least a pixel, so keys stay apart when the image is tiny.
+        let gap = (KEY_GAP_MM * self.scale).round().max(1.0) as usize;
+        for ((x, y), rect) in self.rects.iter() {
+            let Some(color) = canvas.get(*x, *y) else {
+                continue;
+            };
+            let color = color.to_quantized();
+            let to_px = |mm: f64, origin: f64, max: usize| {
+                (((mm - origin) * self.scale).round().max(0.0) as usize).min(max)
+            };
+            let x0 = to_px(rect.x, self.origin.0, self.width) + gap / 2;
+            let x1 =
+                to_px(rect.x + rect.w, self.origin.0, self.width).saturating_sub(gap - gap / 2);
+            let y0 = to_px(rect.y, self.origin.1, self.height) + gap / 2;
+            let y1 =
+                to_px(rect.y + rect.h, self.origin.1, self.height).saturating_sub(gap - gap / 2);
+            for py in y0..y1 {
+                for px in x0..x1 {
+                    let i = (py * self.width + px) * 3;
+                    pixels[i..i + 3].copy_from_slice(&color);
+                }
+            }
+        }
+        pixels
+    }
+}
+
+/// Run an effect for some time & write it to a GIF, or a PNG sequence in a directory.
+pub fn render(config: &Config, layout: Option<&Layout>, args: &RenderArgs) -> Result<(), Error> {
+    let RenderArgs {
+        effect,
+        output,
+        seconds,
+        fps,
+        scale,
+        presses,
+    } = args;
+    let (seconds, fps) = (*seconds, *fps);
+    if fps == 0 {
+        bail!("FPS must be more than 0");
+    }
+    let image = KeyImage::new(
+        cell_rects(layout, config.layout_variant.as_deref())?,
+        *scale as f64,
+    );
+    let mapper = crate::load_matrix_mapper(config, layout)?;
+    let mut renderer = FrameRenderer::new(config, mapper);
+    if !renderer.cycler_mut().set_effect(effect) {
+        bail!(
+            "Unknown effect {effect:?}, expected one of: {}",
+            renderer.cycler().effect_identifiers().join(", ")
+        );
+    }
+
+    let mut script = PressScript::new(presses);
+
+    let is_gif = output.extension().is_some_and(|ext| ext == "gif");
+    let mut gif = if is_gif {
+        let file = File::create(output).with_context(|| format!("Failed to create {output:?}"))?;
+        let mut encoder = gif::Encoder::new(
+            BufWriter::new(file),
+            image.width.try_into()?,
+            image.height.try_into()?,
+            &[],
+        )?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+        Some(encoder)
+    } else {
+        std::fs::create_dir_all(output)
+            .with_context(|| format!("Failed to create directory {output:?}"))?;
+        None
+    };
+
+    let frames = (seconds * fps as f32).round() as u32;
+    let dt = Duration::from_secs_f64(1.0 / fps as f64);
+    for frame in 0..frames {
+        let inputs = script.inputs(frame as f32 / fps as f32, renderer.mapper());
+
+        let time = renderer.clock.step(dt);
+        let pixels = image.draw(renderer.render(&inputs, time)?);
+
+        if let Some(gif) = gif.as_mut() {
+            let mut gif_frame =
+                gif::Frame::from_rgb_speed(image.width as u16, image.height as u16, &pixels, 10);
+            gif_frame.delay = (100.0 / fps as f32).round() as u16;
+            gif.write_frame(&gif_frame)?;
+        } else {
+            let path = output.join(format!("frame_{frame:04}.png"));
+            let file = File::create(&path).with_context(|| format!("Failed to create {path:?}"))?;
+            let mut encoder = png::Encoder::new(
+                BufWriter::new(file),
+                image.width as u32,
+                image.height as u32,
+            );
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.write_header()?.write_image_data(&pixels)?;
+        }
+    }
+
+    log::info!("Rendered {frames} frames to {output:?}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use openrazer::KeyCode;
+
+    use super::ScriptedPress;
+
+    #[test]
+    fn scripted_press() {
+        assert_eq!(
+            "1.5:KEY_A".parse::<ScriptedPress>(),
+            Ok(ScriptedPress {
+                time: 1.5,
+                key: KeyCode::KEY_A,
+                hold: 0.1
+            })
+        );
+        assert_eq!(
+            "0:KEY_SPACE:2"
+                .parse::<ScriptedPress>()
+                .map(|press| press.hold),
+            Ok(2.0)
+        );
+        assert!("KEY_A".parse::<ScriptedPress>().is_err());
+        assert!("-1:KEY_A".parse::<ScriptedPress>().is_err());
+        assert!("inf:KEY_A".parse::<ScriptedPress>().is_err());
+        assert!("1:KEY_A:inf".parse::<ScriptedPress>().is_err());
+        assert!("1:KEY_NOPE".parse::<ScriptedPress>().is_err());
+    }
+}
@@ -0,0 +1,69 @@
+//! Frame deadlines for the main loop.
+
+use std::time::{Duration, Instant};
+
+/// Deadlines on a fixed grid from the start, so the frame rate doesn't drift with how long frames
+/// take. Frames we fell behind on are skipped instead of drawn late all at once.
+#[derive(Debug)]
+pub struct FrameScheduler {
+    interval: Duration,
+    next: Instant,
+}
+
+impl FrameScheduler {
+    pub fn new(interval: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            next: now + interval,
+        }
+    }
+
+    /// When the next frame should be drawn.
+    pub fn deadline(&self) -> Instant {
+        self.next
+    }
+
+    /// Move to the next deadline after a frame was drawn, returns how many frames were skipped.
+    pub fn advance(&mut self, now: Instant) -> u32 {
+        if now < self.next {
+            return 0;
+        }
+        let behind = now.duration_since(self.next);
+        let skipped = (behind.as_nanos() / self.interval.as_nanos()) as u32;
+        self.next += self.interval * (skipped + 1);
+        skipped
+    }
+
+    /// Start the grid again from `now`, e.g. after sleeping until an input.
+    pub fn reset(&mut self, now: Instant) {
+        self.next = now + self.interval;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::FrameScheduler;
+
+    #[test]
+    fn deadlines() {
+        let interval = Duration::from_millis(10);
+        let start = Instant::now();
+        let mut scheduler = FrameScheduler::new(interval, start);
+        assert_eq!(scheduler.deadline(), start + interval);
+
+        // Frames taking different amounts of time don't move the grid.
+        assert_eq!(scheduler.advance(start + Duration::from_millis(3)), 0);
+        assert_eq!(scheduler.deadline(), start + interval);
+        assert_eq!(scheduler.advance(start + Duration::from_millis(11)), 0);
+        assert_eq!(scheduler.deadline(), start + interval * 2);
+
+        // Falling behind skips frames.
+        assert_eq!(scheduler.advance(start + Duration::from_millis(45)), 2);
+        assert_eq!(scheduler.deadline(), start + interval * 5);
+
+        scheduler.reset(start + Duration::from_millis(100));
+        assert_eq!(scheduler.deadline(), start + Duration::from_millis(110));
+    }
+}
@@ -0,0 +1,362 @@
+//! Session lock & sleep from logind, so keys typed on the lock screen aren't shown.
+
+// Only the tests' mock bus is used without logind.
+#![cfg_attr(not(feature = "logind"), allow(dead_code))]
+
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use anyhow::Error;
+
+/// Signals & property changes from logind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// `Lock`/`Unlock` signals & `LockedHint` changes of our session.
+    Locked(bool),
+    /// `PrepareForSleep`, `true` before suspending & `false` after resuming.
+    Sleeping(bool),
+}
+
+/// Source of [`SessionEvent`]s, logind over D-Bus or a mock in tests.
+pub trait LoginBus: Send + 'static {
+    /// If the session is locked now.
+    fn locked_hint(&self) -> Result<bool, Error>;
+
+    /// Send events until the bus closes or `events` is dropped, blocks.
+    fn watch(self, events: Sender<SessionEvent>) -> Result<(), Error>;
+}
+
+/// What changed since the last [`SessionWatcher::poll`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionChange {
+    Locked,
+    Unlocked,
+    Sleeping,
+    Resumed,
+}
+
+/// Follows a [`LoginBus`] on a background thread.
+#[derive(Debug)]
+pub struct SessionWatcher {
+    events: Receiver<SessionEvent>,
+    locked: bool,
+    sleeping: bool,
+}
+
+impl SessionWatcher {
+    /// The first [`SessionWatcher::poll`] reports [`SessionChange::Locked`] if the session is
+    /// already locked. If following the bus fails later, the session stays locked.
+    pub fn start<B: LoginBus>(bus: B) -> Result<Self, Error> {
+        let (sender, events) = mpsc::channel();
+        sender.send(SessionEvent::Locked(bus.locked_hint()?))?;
+        std::thread::Builder::new()
+            .name("session".to_owned())
+            .spawn(move || {
+                if let Err(err) = bus.watch(sender.clone()) {
+                    log::error!("Stopped following the session, hiding key presses: {err:#}");
+                    let _ = sender.send(SessionEvent::Locked(true));
+                }
+            })?;
+        Ok(Self {
+            events,
+            locked: false,
+            sleeping: false,
+        })
+    }
+
+    /// Locked for good, for when the session can't be followed so key presses are never shown.
+    pub fn locked() -> Self {
+        let (sender, events) = mpsc::channel();
+        let _ = sender.send(SessionEvent::Locked(true));
+        Self {
+            events,
+            locked: false,
+            sleeping: false,
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn is_sleeping(&self) -> bool {
+        self.sleeping
+    }
+
+    /// Changes from events received since the last call, repeated states are dropped, e.g. a
+    /// `Lock` signal followed by `LockedHint` turning on.
+    pub fn poll(&mut self) -> Vec<SessionChange> {
+        let events = self.events.try_iter().collect::<Vec<_>>();
+        events
+            .into_iter()
+            .filter_map(|event| self.apply(event))
+            .collect()
+    }
+
+    fn apply(&mut self, event: SessionEvent) -> Option<SessionChange> {
+        match event {
+            SessionEvent::Locked(locked) if locked != self.locked => {
+                self.locked = locked;
+                Some(if locked {
+                    SessionChange::Locked
+                } else {
+                    SessionChange::Unlocked
+                })
+            }
+            SessionEvent::Sleeping(sleeping) if sleeping != self.sleeping => {
+                self.sleeping = sleeping;
+                Some(if sleeping {
+                    SessionChange::Sleeping
+                } else {
+                    SessionChange::Resumed
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Follow the logind session with the ID `session`, or the caller's session without it.
+///
+/// If logind can't be followed the session counts as locked, so key presses are never shown.
+/// [`None`] if the `logind` feature is disabled.
+pub fn watch_logind(session: Option<&str>) -> Option<SessionWatcher> {
+    #[cfg(feature = "logind")]
+    return match logind::Logind::connect(session).and_then(SessionWatcher::start) {
+        Ok(watcher) => Some(watcher),
+        Err(err) => {
+            log::error!(
+                "Failed to follow the session from logind, hiding key presses (Set `session` in \
+                 the config): {err:#}"
+            );
+            Some(SessionWatcher::locked())
+        }
+    };
+    #[cfg(not(feature = "logind"))]
+    {
+        let _ = session;
+        log::warn!("Key presses will be shown while the session is locked");
+        None
+    }
+}
+
+#[cfg(feature = "logind")]
+mod logind {
+    use std::sync::mpsc::Sender;
+
+    use anyhow::{Context, Error};
+
+    use super::{LoginBus, SessionEvent};
+
+    #[zbus::proxy(
+        interface = "org.freedesktop.login1.Session",
+        default_service = "org.freedesktop.login1",
+        // The caller's session, or the user's graphical session for services outside of one.
+        default_path = "/org/freedesktop/login1/session/auto"
+    )]
+    trait Session {
+        #[zbus(signal)]
+        fn lock(&self) -> zbus::Result<()>;
+
+        #[zbus(signal)]
+        fn unlock(&self) -> zbus::Result<()>;
+
+        #[zbus(property)]
+        fn locked_hint(&self) -> zbus::Result<bool>;
+    }
+
+    #[zbus::proxy(
+        interface = "org.freedesktop.login1.Manager",
+        default_service = "org.freedesktop.login1",
+        default_path = "/org/freedesktop/login1"
+    )]
+    trait Manager {
+        fn get_session(&self, session_id: &str) -> zbus::Result<zbus::zvariant::OwnedObjectPath>;
+
+        #[zbus(signal)]
+        fn prepare_for_sleep(&self, start: bool) -> zbus::Result<()>;
+    }
+
+    /// logind on the system bus.
+    pub struct Logind {
+        session: SessionProxyBlocking<'static>,
+        manager: ManagerProxyBlocking<'static>,
+    }
+
+    impl Logind {
+        /// `session` is a session ID, e.g. `"2"` from `loginctl list-sessions`.
+        pub fn connect(session: Option<&str>) -> Result<Self, Error> {
+            let connection = zbus::blocking::Connection::system()
+                .context("Failed to connect to the system bus")?;
+            let manager = ManagerProxyBlocking::new(&connection)?;
+            let session = match session {
+                Some(id) => SessionProxyBlocking::builder(&connection)
+                    .path(
+                        manager
+                            .get_session(id)
+                            .with_context(|| format!("Unknown session {id:?}"))?,
+                    )?
+                    .build()?,
+                None => SessionProxyBlocking::new(&connection)?,
+            };
+            Ok(Self { session, manager })
+        }
+    }
+
+    impl LoginBus for Logind {
+        fn locked_hint(&self) -> Result<bool, Error> {
+            self.session
+                .locked_hint()
+                .context("Failed to get LockedHint of the session")
+        }
+
+        fn watch(self, events: Sender<SessionEvent>) -> Result<(), Error> {
+            let locks = self.session.receive_lock()?;
+            let unlocks = self.session.receive_unlock()?;
+            let hints = self.session.receive_locked_hint_changed();
+            let sleeps = self.manager.receive_prepare_for_sleep()?;
+
+            // The blocking iterators each need a thread, the first to end stops watching.
+            let (done, finished) = std::sync::mpsc::channel();
+            forward(locks, &events, &done, |_| Some(SessionEvent::Locked(true)));
+            forward(unlocks, &events, &done, |_| {
+                Some(SessionEvent::Locked(false))
+            });
+            forward(hints, &events, &done, |change| {
+                change.get().ok().map(SessionEvent::Locked)
+            });
+            forward(sleeps, &events, &done, |signal| {
+                signal
+                    .args()
+                    .ok()
+                    .map(|args| SessionEvent::Sleeping(args.start))
+            });
+            let _ = finished.recv();
+            anyhow::bail!("logind closed the connection")
+        }
+    }
+
+    fn forward<I: Iterator + Send + 'static>(
+        items: I,
+        events: &Sender<SessionEvent>,
+        done: &Sender<()>,
+        to_event: impl Fn(I::Item) -> Option<SessionEvent> + Send + 'static,
+    ) {
+        let (events, done) = (events.clone(), done.clone());
+        std::thread::spawn(move || {
+            for event in items.filter_map(to_event) {
+                if events.send(event).is_err() {
+                    break;
+                }
+            }
+            let _ = done.send(());
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::mpsc::Sender, time::Duration};
+
+    use anyhow::{bail, Error};
+
+    use super::{LoginBus, SessionChange, SessionEvent, SessionWatcher};
+
+    struct MockBus {
+        locked: bool,
+        events: Vec<SessionEvent>,
+    }
+
+    impl LoginBus for MockBus {
+        fn locked_hint(&self) -> Result<bool, Error> {
+            Ok(self.locked)
+        }
+
+        fn watch(self, events: Sender<SessionEvent>) -> Result<(), Error> {
+            for event in self.events {
+                events.send(event)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Fails after sending its events, like logind closing the connection.
+    struct FailingBus(Vec<SessionEvent>);
+
+    impl LoginBus for FailingBus {
+        fn locked_hint(&self) -> Result<bool, Error> {
+            Ok(false)
+        }
+
+        fn watch(self, events: Sender<SessionEvent>) -> Result<(), Error> {
+            for event in self.0 {
+                events.send(event)?;
+            }
+            bail!("Closed")
+        }
+    }
+
+    /// Changes until the mock's thread has sent everything & dropped its sender.
+    fn poll_all(watcher: &mut SessionWatcher) -> Vec<SessionChange> {
+        let mut changes = Vec::new();
+        while let Ok(event) = watcher.events.recv_timeout(Duration::from_secs(10)) {
+            changes.extend(watcher.apply(event));
+        }
+        changes
+    }
+
+    #[test]
+    fn watcher() {
+        let mut watcher = SessionWatcher::start(MockBus {
+            locked: false,
+            events: vec![
+                SessionEvent::Locked(true),
+                // LockedHint follows the Lock signal.
+                SessionEvent::Locked(true),
+                SessionEvent::Sleeping(true),
+                SessionEvent::Sleeping(false),
+                SessionEvent::Locked(false),
+                SessionEvent::Locked(false),
+            ],
+        })
+        .unwrap();
+        assert_eq!(
+            poll_all(&mut watcher),
+            [
+                SessionChange::Locked,
+                SessionChange::Sleeping,
+                SessionChange::Resumed,
+                SessionChange::Unlocked,
+            ]
+        );
+        assert!(!watcher.is_locked() && !watcher.is_sleeping());
+
+        let mut watcher = SessionWatcher::start(MockBus {
+            locked: true,
+            events: Vec::new(),
+        })
+        .unwrap();
+        assert_eq!(poll_all(&mut watcher), [SessionChange::Locked]);
+        assert!(watcher.is_locked());
+    }
+
+    #[test]
+    fn fails_locked() {
+        let mut watcher = SessionWatcher::start(FailingBus(vec![
+            SessionEvent::Locked(true),
+            SessionEvent::Locked(false),
+        ]))
+        .unwrap();
+        assert_eq!(
+            poll_all(&mut watcher),
+            [
+                SessionChange::Locked,
+                SessionChange::Unlocked,
+                SessionChange::Locked
+            ]
+        );
+
+        let mut watcher = SessionWatcher::locked();
+        assert_eq!(poll_all(&mut watcher), [SessionChange::Locked]);
+        assert!(watcher.is_locked());
+    }
+}
@@ -0,0 +1,497 @@
+//! Keyboard simulator in the terminal, for effects without a Razer keyboard.
+//!
+//! Keys typed in the terminal are fed to the effects. Terminals with the kitty keyboard protocol
+//! report releases & modifier keys, on others keys are released shortly after they are pressed.
+//!
+//! With `--attach` it's a live view of the frames a running my-keyboard sends to the keyboard,
+//! from its control socket.
+
+use std::{
+    io::{BufRead, BufReader, Stdout, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    sync::mpsc::{self, TryRecvError},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Error};
+use crossterm::{
+    cursor,
+    event::{
+        self, Event, KeyCode as TermKeyCode, KeyEvent as TermKeyEvent, KeyEventKind, KeyModifiers,
+        KeyboardEnhancementFlags, ModifierKeyCode, PopKeyboardEnhancementFlags,
+        PushKeyboardEnhancementFlags,
+    },
+    queue,
+    style::{self, Print},
+    terminal,
+};
+use openrazer::{Canvas, Color, KeyCode, KeyEvent, Layout};
+
+use crate::{
+    config::Config,
+    frame::FrameRenderer,
+    input::InputTracker,
+    protocol::{Request, Response},
+    render::{cell_rects, KeyImage},
+    TARGET_UPDATE_RATE,
+};
+
+/// How long keys are held for on terminals that don't report releases.
+const SIMULATED_HOLD: Duration = Duration::from_millis(150);
+
+const HELP: &str = "Ctrl+N: Next effect  Ctrl+P: Pause  Ctrl+C: Quit";
+const ATTACH_HELP: &str = "Ctrl+C: Quit";
+
+/// Raw mode & the alternate screen, restored when dropped.
+struct Terminal {
+    stdout: Stdout,
+    enhanced: bool,
+}
+
+impl Terminal {
+    fn new() -> Result<Self, Error> {
+        let mut stdout = std::io::stdout();
+        terminal::enable_raw_mode()?;
+        queue!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+        let enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if enhanced {
+            queue!(
+                stdout,
+                PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                        | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                        | KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES
+                )
+            )?;
+        }
+        stdout.flush()?;
+        Ok(Self { stdout, enhanced })
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        if self.enhanced {
+            let _ = queue!(self.stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = queue!(self.stdout, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = self.stdout.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Evdev key for a terminal key, & if shift is needed to type it on a US layout.
+fn map_key(code: TermKeyCode) -> Option<(KeyCode, bool)> {
+    const UNSHIFTED: &str = "1234567890-=[]\\;',./`";
+    const SHIFTED: &str = "!@#$%^&*()_+{}|:\"<>?~";
+    const SYMBOL_KEYS: [KeyCode; 21] = [
+        KeyCode::KEY_1,
+        KeyCode::KEY_2,
+        KeyCode::KEY_3,
+        KeyCode::KEY_4,
+        KeyCode::KEY_5,
+        KeyCode::KEY_6,
+        KeyCode::KEY_7,
+        KeyCode::KEY_8,
+        KeyCode::KEY_9,
+        KeyCode::KEY_0,
+        KeyCode::KEY_MINUS,
+        KeyCode::KEY_EQUAL,
+        KeyCode::KEY_LEFTBRACE,
+        KeyCode::KEY_RIGHTBRACE,
+        KeyCode::KEY_BACKSLASH,
+        KeyCode::KEY_SEMICOLON,
+        KeyCode::KEY_APOSTROPHE,
+        KeyCode::KEY_COMMA,
+        KeyCode::KEY_DOT,
+        KeyCode::KEY_SLASH,
+        KeyCode::KEY_GRAVE,
+    ];
+
+    let key = match code {
+        TermKeyCode::Char(' ') => KeyCode::KEY_SPACE,
+        TermKeyCode::Char(c) if c.is_ascii_alphabetic() => {
+            let name = format!("KEY_{}", c.to_ascii_uppercase());
+            return Some((name.parse().ok()?, c.is_ascii_uppercase()));
+        }
+        TermKeyCode::Char(c) => {
+            if let Some(i) = UNSHIFTED.find(c) {
+                return Some((SYMBOL_KEYS[i], false));
+            }
+            return SHIFTED.find(c).map(|i| (SYMBOL_KEYS[i], true));
+        }
+        TermKeyCode::F(n @ 1..=12) => format!("KEY_F{n}").parse().ok()?,
+        TermKeyCode::Backspace => KeyCode::KEY_BACKSPACE,
+        TermKeyCode::Enter => KeyCode::KEY_ENTER,
+        TermKeyCode::Tab | TermKeyCode::BackTab => KeyCode::KEY_TAB,
+        TermKeyCode::Esc => KeyCode::KEY_ESC,
+        TermKeyCode::Left => KeyCode::KEY_LEFT,
+        TermKeyCode::Right => KeyCode::KEY_RIGHT,
+        TermKeyCode::Up => KeyCode::KEY_UP,
+        TermKeyCode::Down => KeyCode::KEY_DOWN,
+        TermKeyCode::Home => KeyCode::KEY_HOME,
+        TermKeyCode::End => KeyCode::KEY_END,
+        TermKeyCode::PageUp => KeyCode::KEY_PAGEUP,
+        TermKeyCode::PageDown => KeyCode::KEY_PAGEDOWN,
+        TermKeyCode::Insert => KeyCode::KEY_INSERT,
+        TermKeyCode::Delete => KeyCode::KEY_DELETE,
+        TermKeyCode::CapsLock => KeyCode::KEY_CAPSLOCK,
+        TermKeyCode::ScrollLock => KeyCode::KEY_SCROLLLOCK,
+        TermKeyCode::NumLock => KeyCode::KEY_NUMLOCK,
+        TermKeyCode::PrintScreen => KeyCode::KEY_SYSRQ,
+        TermKeyCode::Pause => KeyCode::KEY_PAUSE,
+        TermKeyCode::Menu => KeyCode::KEY_COMPOSE,
+        TermKeyCode::Modifier(modifier) => match modifier {
+            ModifierKeyCode::LeftShift => KeyCode::KEY_LEFTSHIFT,
+            ModifierKeyCode::LeftControl => KeyCode::KEY_LEFTCTRL,
+            ModifierKeyCode::LeftAlt => KeyCode::KEY_LEFTALT,
+            ModifierKeyCode::LeftSuper | ModifierKeyCode::LeftMeta => KeyCode::KEY_LEFTMETA,
+            ModifierKeyCode::RightShift => KeyCode::KEY_RIGHTSHIFT,
+            ModifierKeyCode::RightControl => KeyCode::KEY_RIGHTCTRL,
+            ModifierKeyCode::RightAlt | ModifierKeyCode::IsoLevel3Shift => KeyCode::KEY_RIGHTALT,
+            ModifierKeyCode::RightSuper | ModifierKeyCode::RightMeta => KeyCode::KEY_RIGHTMETA,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some((key, false))
+}
+
+/// Turns terminal key events into evdev key events.
+#[derive(Debug, Default)]
+struct TerminalKeys {
+    /// Keys that will be released, if the terminal doesn't report releases.
+    held: Vec<(KeyCode, Instant)>,
+}
+
+impl TerminalKeys {
+    fn process(&mut self, event: TermKeyEvent, enhanced: bool, now: Instant) -> Vec<KeyEvent> {
+        let Some((key, shifted)) = map_key(event.code) else {
+            return Vec::new();
+        };
+        if enhanced {
+            let value = match event.kind {
+                KeyEventKind::Press => 1,
+                KeyEventKind::Repeat => 2,
+                KeyEventKind::Release => 0,
+            };
+            return vec![KeyEvent::new(key, value)];
+        }
+
+        let mut keys = [
+            (
+                event.modifiers.contains(KeyModifiers::SHIFT) || shifted,
+                KeyCode::KEY_LEFTSHIFT,
+            ),
+            (
+                event.modifiers.contains(KeyModifiers::CONTROL),
+                KeyCode::KEY_LEFTCTRL,
+            ),
+            (
+                event.modifiers.contains(KeyModifiers::ALT),
+                KeyCode::KEY_LEFTALT,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(held, modifier)| held.then_some(modifier))
+        .collect::<Vec<_>>();
+        keys.push(key);
+
+        let release = now + SIMULATED_HOLD;
+        keys.into_iter()
+            .map(|key| match self.held.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, time)) => {
+                    *time = release;
+                    KeyEvent::new(key, 2)
+                }
+                None => {
+                    self.held.push((key, release));
+                    KeyEvent::new(key, 1)
+                }
+            })
+            .collect()
+    }
+
+    /// Release keys that were held long enough.
+    fn release(&mut self, now: Instant) -> Vec<KeyEvent> {
+        let mut events = Vec::new();
+        self.held.retain(|(key, time)| {
+            let held = *time > now;
+            if !held {
+                events.push(KeyEvent::new(*key, 0));
+            }
+            held
+        });
+        events
+    }
+}
+
+/// Draw an image with half blocks, two pixels per character.
+fn draw(stdout: &mut Stdout, image: &KeyImage, pixels: &[u8], status: &str) -> Result<(), Error> {
+    let rgb = |x: usize, y: usize| {
+        let i = (y * image.width + x) * 3;
+        pixels
+            .get(i..i + 3)
+            .map(|c| style::Color::Rgb {
+                r: c[0],
+                g: c[1],
+                b: c[2],
+            })
+            .unwrap_or(style::Color::Reset)
+    };
+    for row in 0..image.height.div_ceil(2) {
+        queue!(stdout, cursor::MoveTo(0, row as u16))?;
+        let mut last = None;
+        for x in 0..image.width {
+            let colors = (rgb(x, row * 2), rgb(x, row * 2 + 1));
+            if last != Some(colors) {
+                queue!(
+                    stdout,
+                    style::SetForegroundColor(colors.0),
+                    style::SetBackgroundColor(colors.1),
+                )?;
+                last = Some(colors);
+            }
+            queue!(stdout, Print('▀'))?;
+        }
+    }
+    queue!(
+        stdout,
+        style::ResetColor,
+        cursor::MoveTo(0, image.height.div_ceil(2) as u16),
+        terminal::Clear(terminal::ClearType::CurrentLine),
+        Print(status),
+    )?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Fit the keyboard in the terminal, leaving a line for the status.
+fn fit_image(config: &Config, layout: Option<&Layout>) -> Result<KeyImage, Error> {
+    let (columns, rows) = terminal::size()?;
+    Ok(KeyImage::fit(
+        cell_rects(layout, config.layout_variant.as_deref())?,
+        columns as usize,
+        (rows as usize).saturating_sub(1) * 2,
+    ))
+}
+
+pub fn simulate(config: &Config, layout: Option<&Layout>) -> Result<(), Error> {
+    let mapper = crate::load_matrix_mapper(config, layout)?;
+    let mut renderer = FrameRenderer::new(config, mapper);
+    renderer.next_effect();
+
+    let mut terminal = Terminal::new()?;
+    let mut image = fit_image(config, layout)?;
+    let mut terminal_keys = TerminalKeys::default();
+    let mut input_tracker = InputTracker::default();
+
+    loop {
+        let next_frame_time = Instant::now() + TARGET_UPDATE_RATE;
+
+        let mut key_events = Vec::new();
+        while event::poll(next_frame_time.saturating_duration_since(Instant::now()))? {
+            match event::read()? {
+                Event::Key(event)
+                    if event.kind == KeyEventKind::Press
+                        && event.modifiers.contains(KeyModifiers::CONTROL) =>
+                {
+                    match event.code {
+                        TermKeyCode::Char('c') => return Ok(()),
+                        TermKeyCode::Char('n') => renderer.next_effect(),
+                        TermKeyCode::Char('p') => {
+                            let paused = renderer.clock.is_paused();
+                            renderer.clock.set_paused(!paused);
+                        }
+                        _ => key_events.extend(terminal_keys.process(
+                            event,
+                            terminal.enhanced,
+                            Instant::now(),
+                        )),
+                    }
+                }
+                Event::Key(event) => key_events.extend(terminal_keys.process(
+                    event,
+                    terminal.enhanced,
+                    Instant::now(),
+                )),
+                Event::Resize(_, _) => {
+                    image = fit_image(config, layout)?;
+                    queue!(terminal.stdout, terminal::Clear(terminal::ClearType::All))?;
+                }
+                _ => {}
+            }
+        }
+        key_events.extend(terminal_keys.release(Instant::now()));
+
+        let inputs = key_events
+            .into_iter()
+            .flat_map(|event| input_tracker.process(*event, renderer.mapper()))
+            .collect::<Vec<_>>();
+        let time = renderer.clock.tick(Instant::now());
+        let pixels = image.draw(renderer.render(&inputs, time)?);
+
+        let status = format!(
+            "{}{}  {HELP}",
+            renderer
+                .cycler()
+                .current_effect_identifier()
+                .unwrap_or("None"),
+            if renderer.clock.is_paused() {
+                " (Paused)"
+            } else {
+                ""
+            },
+        );
+        draw(&mut terminal.stdout, &image, &pixels, &status)?;
+    }
+}
+
+/// Canvas of a [`Response::Frame`], [`None`] if the colors don't fit the size.
+fn frame_canvas(width: usize, height: usize, colors: &str) -> Option<Canvas> {
+    if !colors.is_ascii() || colors.len() != width * height * 6 {
+        return None;
+    }
+    let mut canvas = Canvas::new(width, height);
+    for ((_, _, color), hex) in canvas.iter_mut().zip(colors.as_bytes().chunks(6)) {
+        *color = Color::from_hex(std::str::from_utf8(hex).ok()?)?;
+    }
+    Some(canvas)
+}
+
+/// Draw the frames of a running my-keyboard, until Ctrl+C or it stops.
+pub fn attach(config: &Config, layout: Option<&Layout>, socket: &Path) -> Result<(), Error> {
+    let stream = UnixStream::connect(socket)
+        .with_context(|| format!("Failed to connect to {socket:?}, is my-keyboard running?"))?;
+    let mut request = serde_json::to_string(&Request::SubscribeFrames)?;
+    request.push('\n');
+    (&stream).write_all(request.as_bytes())?;
+
+    // Read on a thread so the terminal stays responsive.
+    let (sender, frames) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            let frame = match line.map(|line| serde_json::from_str(&line)) {
+                Ok(Ok(Response::Ok)) => continue,
+                Ok(Ok(Response::Frame {
+                    width,
+                    height,
+                    colors,
+                })) => frame_canvas(width, height, &colors)
+                    .with_context(|| format!("Invalid {width}x{height} frame from my-keyboard")),
+                Ok(Ok(Response::Error { message })) => Err(Error::msg(message)),
+                Ok(Ok(response)) => Err(Error::msg(format!("Unexpected response {response:?}"))),
+                Ok(Err(err)) => Err(Error::new(err).context("Invalid response from my-keyboard")),
+                Err(err) => Err(err.into()),
+            };
+            let failed = frame.is_err();
+            if sender.send(frame).is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let mut terminal = Terminal::new()?;
+    let mut image = fit_image(config, layout)?;
+    let mut canvas = Canvas::new(0, 0);
+    let status = format!("Attached to {}  {ATTACH_HELP}", socket.display());
+    let mut redraw = true;
+    loop {
+        // Wait for the next event or frame, then handle every event that is waiting.
+        let mut timeout = TARGET_UPDATE_RATE;
+        while event::poll(timeout)? {
+            timeout = Duration::ZERO;
+            match event::read()? {
+                Event::Key(event)
+                    if event.kind == KeyEventKind::Press
+                        && event.modifiers.contains(KeyModifiers::CONTROL)
+                        && event.code == TermKeyCode::Char('c') =>
+                {
+                    return Ok(());
+                }
+                Event::Resize(_, _) => {
+                    image = fit_image(config, layout)?;
+                    queue!(terminal.stdout, terminal::Clear(terminal::ClearType::All))?;
+                    redraw = true;
+                }
+                _ => {}
+            }
+        }
+        loop {
+            match frames.try_recv() {
+                Ok(frame) => {
+                    canvas = frame?;
+                    redraw = true;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    bail!("my-keyboard closed the control socket")
+                }
+            }
+        }
+        if redraw {
+            draw(&mut terminal.stdout, &image, &image.draw(&canvas), &status)?;
+            redraw = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crossterm::event::{KeyCode as TermKeyCode, KeyEvent as TermKeyEvent, KeyModifiers};
+    use openrazer::{Canvas, Color, KeyCode};
+
+    use super::{frame_canvas, TerminalKeys};
+    use crate::{control::frame_response, protocol::Response};
+
+    #[test]
+    fn frames() {
+        let mut canvas = Canvas::new(3, 2);
+        *canvas.get_mut(2, 1).unwrap() = Color::from_quantized(255, 128, 0);
+        let Response::Frame {
+            width,
+            height,
+            colors,
+        } = frame_response(&canvas)
+        else {
+            unreachable!();
+        };
+        assert_eq!((width, height), (3, 2));
+        assert_eq!(colors, "000000".repeat(5) + "FF8000");
+        assert_eq!(frame_canvas(width, height, &colors), Some(canvas));
+
+        assert_eq!(frame_canvas(2, 2, &colors), None);
+        assert_eq!(frame_canvas(1, 1, "00000X"), None);
+        assert_eq!(frame_canvas(1, 1, "0000ü"), None);
+    }
+
+    #[test]
+    fn simulated_release() {
+        let now = Instant::now();
+        let mut keys = TerminalKeys::default();
+        let summary = |events: Vec<openrazer::KeyEvent>| {
+            events
+                .into_iter()
+                .map(|event| event.destructure())
+                .collect::<Vec<_>>()
+        };
+
+        let event = TermKeyEvent::new(TermKeyCode::Char('!'), KeyModifiers::NONE);
+        assert_eq!(
+            summary(keys.process(event, false, now)),
+            [(KeyCode::KEY_LEFTSHIFT, 1), (KeyCode::KEY_1, 1)]
+        );
+        assert_eq!(
+            summary(keys.process(event, false, now)),
+            [(KeyCode::KEY_LEFTSHIFT, 2), (KeyCode::KEY_1, 2)]
+        );
+        assert!(keys.release(now).is_empty());
+        assert_eq!(
+            summary(keys.release(now + Duration::from_secs(1))),
+            [(KeyCode::KEY_LEFTSHIFT, 0), (KeyCode::KEY_1, 0)]
+        );
+    }
+}
@@ -0,0 +1,303 @@
+//! Transitions between effects.
+
+use std::time::Duration;
+
+use anyhow::Error;
+use openrazer::{Canvas, Color, MatrixMapper};
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::{
+    effects::{Effect, EffectContext},
+    util::lerp,
+};
+
+/// Width of the soft edge of wipes & radial reveals, as a fraction of the transition.
+const EDGE: f32 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransitionKind {
+    /// Switch instantly.
+    None,
+    #[default]
+    Crossfade,
+    WipeLeft,
+    WipeRight,
+    WipeUp,
+    WipeDown,
+    /// Reveal in a circle from the last key pressed.
+    Radial,
+    /// Keys switch one by one in a random order.
+    Dissolve,
+}
+
+/// The previous effect, still running until the transition is done.
+#[derive(Debug)]
+pub struct Transition {
+    kind: TransitionKind,
+    duration: Duration,
+    /// Frame clock time of the first update.
+    start: Option<Duration>,
+    from: Box<dyn Effect>,
+    frame: Canvas,
+    origin: Option<(f32, f32)>,
+    /// When each cell switches for [`TransitionKind::Dissolve`].
+    thresholds: Vec<f32>,
+}
+
+impl Transition {
+    /// `origin` is where radial reveals start from, the middle of the keyboard if [`None`].
+    pub fn new(
+        kind: TransitionKind,
+        duration: Duration,
+        from: Box<dyn Effect>,
+        origin: Option<(f32, f32)>,
+    ) -> Self {
+        Self {
+            kind,
+            duration,
+            start: None,
+            from,
+            frame: Canvas::new(0, 0),
+            origin,
+            thresholds: Vec::new(),
+        }
+    }
+
+    pub fn progress(&self, now: Duration) -> f32 {
+        if self.kind == TransitionKind::None || self.duration.is_zero() {
+            return 1.0;
+        }
+        let Some(start) = self.start else {
+            return 0.0;
+        };
+        (now.saturating_sub(start).as_secs_f32() / self.duration.as_secs_f32()).clamp(0.0, 1.0)
+    }
+
+    pub fn is_done(&self, now: Duration) -> bool {
+        self.progress(now) >= 1.0
+    }
+
+    /// How much of the new effect is shown on a cell.
+    fn amount(&self, t: f32, x: usize, y: usize, mapper: &MatrixMapper) -> f32 {
+        let ((min_x, min_y), (max_x, max_y)) = mapper.bounds();
+        let (px, py) = mapper.position(x, y);
+        let along = |v: f32, min: f32, max: f32| {
+            if max > min {
+                ((v - min) / (max - min)).clamp(0.0, 1.0)
+            } else {
+                0.0
+            }
+        };
+        // Distance the edge has to travel (0 to 1), edge moves from 0 to 1 + EDGE.
+        let reveal = |dist: f32| ((t * (1.0 + EDGE) - dist) / EDGE).clamp(0.0, 1.0);
+
+        match self.kind {
+            TransitionKind::None => 1.0,
+            TransitionKind::Crossfade => t,
+            TransitionKind::WipeLeft => reveal(1.0 - along(px, min_x, max_x)),
+            TransitionKind::WipeRight => reveal(along(px, min_x, max_x)),
+            TransitionKind::WipeUp => reveal(1.0 - along(py, min_y, max_y)),
+            TransitionKind::WipeDown => reveal(along(py, min_y, max_y)),
+            TransitionKind::Radial => {
+                let (ox, oy) = self
+                    .origin
+                    .unwrap_or(((min_x + max_x) / 2.0, (min_y + max_y) / 2.0));
+                let dist = |x: f32, y: f32| f32::sqrt((x - ox).powi(2) + (y - oy).powi(2));
+                let max_dist = [
+                    (min_x, min_y),
+                    (max_x, min_y),
+                    (min_x, max_y),
+                    (max_x, max_y),
+                ]
+                .into_iter()
+                .map(|(x, y)| dist(x, y))
+                .fold(0.0, f32::max);
+                if max_dist > 0.0 {
+                    reveal(dist(px, py) / max_dist)
+                } else {
+                    t
+                }
+            }
+            TransitionKind::Dissolve => {
+                if t >= self.thresholds[x + y * self.frame.width()] {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    /// Update the previous effect & mix it into the new effect's frame.
+    pub fn update(&mut self, to: &mut Canvas, ctx: &EffectContext) -> Result<(), Error> {
+        self.start.get_or_insert(ctx.time.elapsed);
+        self.frame.resize(to.width(), to.height());
+        if self.thresholds.len() != to.width() * to.height() {
+            self.thresholds = (0..to.width() * to.height())
+                .map(|_| ctx.rng().random::<f32>())
+                .collect();
+        }
+        self.from.update(&mut self.frame, ctx)?;
+
+        let t = self.progress(ctx.time.elapsed);
+        let mapper = ctx.mapper;
+        for (x, y, color) in to.iter_mut() {
+            if let Some(from) = self.frame.get(x, y) {
+                *color = lerp(*from, *color, self.amount(t, x, y, mapper));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An effect that is still transitioning in, so the next transition starts from the mixed frames
+/// instead of jumping to the effect.
+#[derive(Debug)]
+pub struct Transitioning {
+    /// [`None`] while transitioning to no effect.
+    pub effect: Option<Box<dyn Effect>>,
+    pub transition: Transition,
+}
+
+impl Effect for Transitioning {
+    fn identifier(&self) -> &str {
+        self.effect
+            .as_ref()
+            .map_or("transitioning", |effect| effect.identifier())
+    }
+
+    fn update(&mut self, matrix: &mut Canvas, ctx: &EffectContext) -> Result<(), Error> {
+        match self.effect.as_mut() {
+            Some(effect) => effect.update(matrix, ctx)?,
+            None => matrix.fill(Color::new(0.0, 0.0, 0.0)),
+        }
+        if !self.transition.is_done(ctx.time.elapsed) {
+            self.transition.update(matrix, ctx)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, time::Duration};
+
+    use openrazer::{Canvas, Color};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{Transition, TransitionKind, Transitioning};
+    use crate::{
+        clock::FrameClock,
+        effects::{EffectContext, EffectSolid},
+        util::lerp,
+    };
+
+    const RED: Color = Color::new(1.0, 0.0, 0.0);
+    const BLUE: Color = Color::new(0.0, 0.0, 1.0);
+    const GREEN: Color = Color::new(0.0, 1.0, 0.0);
+
+    /// Frames of `transition` into `to`, one for each step of the clock in seconds.
+    fn frames(
+        transition: &mut Transition,
+        to: Color,
+        clock: &mut FrameClock,
+        steps: &[f32],
+    ) -> Vec<Canvas> {
+        let rng = RefCell::new(StdRng::seed_from_u64(0));
+        steps
+            .iter()
+            .map(|step| {
+                let ctx = EffectContext::test(&rng, clock.step(Duration::from_secs_f32(*step)));
+                let mut canvas = Canvas::new(22, 6);
+                canvas.fill(to);
+                transition.update(&mut canvas, &ctx).unwrap();
+                canvas
+            })
+            .collect()
+    }
+
+    /// From red, in 1 second.
+    fn transition(kind: TransitionKind, origin: Option<(f32, f32)>) -> Transition {
+        Transition::new(
+            kind,
+            Duration::from_secs(1),
+            Box::new(EffectSolid::new(RED)),
+            origin,
+        )
+    }
+
+    #[test]
+    fn progress() {
+        let kinds = [
+            TransitionKind::Crossfade,
+            TransitionKind::WipeLeft,
+            TransitionKind::WipeRight,
+            TransitionKind::WipeUp,
+            TransitionKind::WipeDown,
+            TransitionKind::Radial,
+            TransitionKind::Dissolve,
+        ];
+        for kind in kinds {
+            let mut transition = transition(kind, Some((0.0, 0.0)));
+            let clock = &mut FrameClock::default();
+            let frames = frames(&mut transition, BLUE, clock, &[0.0, 0.5, 0.5]);
+            let [start, half, end] = frames.as_slice() else {
+                unreachable!();
+            };
+            assert!(start.iter().all(|(_, _, color)| *color == RED), "{kind:?}");
+            assert!(end.iter().all(|(_, _, color)| *color == BLUE), "{kind:?}");
+            assert!(transition.is_done(Duration::from_secs(1)));
+
+            // Cells that have switched at half way, the opposite corners for wipes.
+            let at = |x, y| *half.get(x, y).unwrap();
+            match kind {
+                TransitionKind::Crossfade => {
+                    let mixed = lerp(RED, BLUE, 0.5);
+                    assert!(half.iter().all(|(_, _, color)| *color == mixed));
+                }
+                TransitionKind::WipeRight => assert_eq!((at(0, 0), at(21, 0)), (BLUE, RED)),
+                TransitionKind::WipeLeft => assert_eq!((at(0, 0), at(21, 0)), (RED, BLUE)),
+                TransitionKind::WipeDown => assert_eq!((at(0, 0), at(0, 5)), (BLUE, RED)),
+                TransitionKind::WipeUp => assert_eq!((at(0, 0), at(0, 5)), (RED, BLUE)),
+                TransitionKind::Radial => assert_eq!((at(0, 0), at(21, 5)), (BLUE, RED)),
+                TransitionKind::Dissolve => {
+                    let switched = half.iter().filter(|(_, _, color)| **color == BLUE).count();
+                    let unswitched = half.iter().filter(|(_, _, color)| **color == RED).count();
+                    assert_eq!(switched + unswitched, 22 * 6);
+                    assert!((33..=99).contains(&switched), "{switched} switched");
+                }
+                _ => unreachable!(),
+            }
+            // Wipes have a soft edge in the middle.
+            if matches!(kind, TransitionKind::WipeRight | TransitionKind::WipeLeft) {
+                assert!(at(11, 0) != RED && at(11, 0) != BLUE, "{kind:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn from_running_transition() {
+        // Red to blue is half way when blue crossfades to green.
+        let clock = &mut FrameClock::default();
+        let mut running = transition(TransitionKind::Crossfade, None);
+        frames(&mut running, BLUE, clock, &[0.0, 0.5]);
+        let mut transition = Transition::new(
+            TransitionKind::Crossfade,
+            Duration::from_secs(1),
+            Box::new(Transitioning {
+                effect: Some(Box::new(EffectSolid::new(BLUE))),
+                transition: running,
+            }),
+            None,
+        );
+        let frames = frames(&mut transition, GREEN, clock, &[0.0, 0.5, 1.0]);
+
+        // Starts from the mixed frame, which keeps moving to blue underneath.
+        let color = |frame: &Canvas| *frame.get(3, 3).unwrap();
+        assert_eq!(color(&frames[0]), lerp(RED, BLUE, 0.5));
+        assert_eq!(color(&frames[1]), lerp(BLUE, GREEN, 0.5));
+        assert_eq!(color(&frames[2]), GREEN);
+    }
+}
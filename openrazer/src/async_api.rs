@@ -0,0 +1,292 @@
+//! Async API for tokio, enabled with the `tokio` feature.
+
+use std::time::Duration;
+
+use futures_core::Stream;
+use tokio_stream::wrappers::ReceiverStream;
+
+#[cfg(feature = "input")]
+pub use input::*;
+
+use crate::{query_razer_devices, OpenRazerError, RazerDevice};
+
+#[cfg(feature = "input")]
+mod input {
+    use std::{
+        os::fd::AsRawFd,
+        pin::Pin,
+        task::{ready, Context, Poll},
+    };
+
+    use futures_core::Stream;
+    use tokio::io::unix::AsyncFd;
+
+    use crate::{EvdevDeviceNonblocking, InputEvent, OpenRazerError, RazerDevice};
+
+    /// Stream of input events from an evdev device.
+    ///
+    /// Ends after an error, e.g. when the device is unplugged.
+    #[derive(Debug)]
+    pub struct EvdevEventStream {
+        device: Option<AsyncFd<EvdevDeviceNonblocking>>,
+    }
+
+    impl EvdevEventStream {
+        /// Must be called inside of a tokio runtime.
+        pub fn new(device: EvdevDeviceNonblocking) -> Result<Self, std::io::Error> {
+            Ok(Self {
+                device: Some(AsyncFd::new(device)?),
+            })
+        }
+    }
+
+    impl Stream for EvdevEventStream {
+        type Item = Result<InputEvent, std::io::Error>;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            poll_next_event(&mut self.device, cx, EvdevDeviceNonblocking::try_next)
+        }
+    }
+
+    /// Read the next event once `device` is readable, `device` is cleared after an error so the
+    /// stream ends.
+    ///
+    /// `try_next` must only return [`None`] once reading would block, as that clears the readiness
+    /// & waits for the next edge.
+    pub(super) fn poll_next_event<T: AsRawFd>(
+        device: &mut Option<AsyncFd<T>>,
+        cx: &mut Context<'_>,
+        mut try_next: impl FnMut(&mut T) -> Result<Option<InputEvent>, std::io::Error>,
+    ) -> Poll<Option<Result<InputEvent, std::io::Error>>> {
+        let Some(fd) = device.as_mut() else {
+            return Poll::Ready(None);
+        };
+
+        let result = loop {
+            let mut guard = match ready!(fd.poll_read_ready_mut(cx)) {
+                Ok(guard) => guard,
+                Err(err) => break Err(err),
+            };
+            match try_next(guard.get_inner_mut()) {
+                Ok(Some(event)) => break Ok(event),
+                Ok(None) => guard.clear_ready(),
+                Err(err) => break Err(err),
+            }
+        };
+
+        if result.is_err() {
+            *device = None;
+        }
+        Poll::Ready(Some(result))
+    }
+
+    impl RazerDevice {
+        /// Same as [`RazerDevice::get_evdev_device`], as a [`Stream`].
+        pub fn get_evdev_stream(&self) -> Result<Option<EvdevEventStream>, OpenRazerError> {
+            Ok(self
+                .get_evdev_device()?
+                .map(EvdevEventStream::new)
+                .transpose()?)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum DeviceWatchEvent {
+    Added(RazerDevice),
+    Removed(RazerDevice),
+    /// Querying devices failed, watching continues.
+    Error(String),
+}
+
+/// Watch for Razer devices being plugged in & out, checking every `interval`.
+///
+/// Devices that are already plugged in are reported as [`DeviceWatchEvent::Added`] first.
+/// Must be called inside of a tokio runtime, stops when the stream is dropped.
+pub fn watch_razer_devices(interval: Duration) -> impl Stream<Item = DeviceWatchEvent> {
+    watch_devices(interval, query_razer_devices)
+}
+
+/// [`watch_razer_devices`] with another way to query devices, for tests.
+fn watch_devices(
+    interval: Duration,
+    query: fn() -> Result<Box<[RazerDevice]>, OpenRazerError>,
+) -> impl Stream<Item = DeviceWatchEvent> {
+    let (tx, rx) = tokio::sync::mpsc::channel(16);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut known: Vec<RazerDevice> = Vec::new();
+
+        loop {
+            interval.tick().await;
+
+            let events = match tokio::task::spawn_blocking(query).await {
+                Ok(Ok(devices)) => {
+                    let devices = devices.into_vec();
+                    let events = diff_devices(&known, &devices);
+                    known = devices;
+                    events
+                }
+                Ok(Err(err)) => vec![DeviceWatchEvent::Error(err.to_string())],
+                // Known devices are kept, the next query may work again.
+                Err(err) => vec![DeviceWatchEvent::Error(format!(
+                    "Querying devices panicked: {err}"
+                ))],
+            };
+
+            for event in events {
+                if tx.send(event).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+/// Events for devices that were unplugged, followed by devices that were plugged in.
+fn diff_devices(known: &[RazerDevice], devices: &[RazerDevice]) -> Vec<DeviceWatchEvent> {
+    let removed = known
+        .iter()
+        .filter(|device| !devices.iter().any(|d| d.sys_path == device.sys_path))
+        .cloned()
+        .map(DeviceWatchEvent::Removed);
+    let added = devices
+        .iter()
+        .filter(|device| !known.iter().any(|d| d.sys_path == device.sys_path))
+        .cloned()
+        .map(DeviceWatchEvent::Added);
+    removed.chain(added).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use tokio_stream::StreamExt;
+
+    use super::{diff_devices, watch_devices, DeviceWatchEvent};
+    use crate::{OpenRazerError, QueryError, RazerDevice};
+
+    fn device(path: &str) -> RazerDevice {
+        RazerDevice {
+            sys_path: PathBuf::from(path),
+            id_product: 0x021e,
+            serial: None,
+            name: None,
+            interfaces: Box::new([]),
+        }
+    }
+
+    fn paths(events: &[DeviceWatchEvent]) -> Vec<String> {
+        events
+            .iter()
+            .map(|event| match event {
+                DeviceWatchEvent::Added(device) => format!("+{}", device.sys_path.display()),
+                DeviceWatchEvent::Removed(device) => format!("-{}", device.sys_path.display()),
+                DeviceWatchEvent::Error(message) => format!("!{message}"),
+            })
+            .collect()
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn diff() {
+        assert_eq!(paths(&diff_devices(&[], &[device("1-1")])), ["+1-1"]);
+        assert_eq!(
+            paths(&diff_devices(
+                &[device("1-1"), device("1-2")],
+                &[device("1-2"), device("1-3")]
+            )),
+            ["-1-1", "+1-3"]
+        );
+        assert!(diff_devices(&[device("1-1")], &[device("1-1")]).is_empty());
+    }
+
+    #[test]
+    fn watch() {
+        static QUERIES: AtomicUsize = AtomicUsize::new(0);
+        fn query() -> Result<Box<[RazerDevice]>, OpenRazerError> {
+            match QUERIES.fetch_add(1, Ordering::Relaxed) {
+                0 => Ok(Box::new([device("1-1")])),
+                1 => Err(QueryError::Malformed("Test").into()),
+                2 => panic!("Test"),
+                _ => Ok(Box::new([device("1-2")])),
+            }
+        }
+
+        let events = runtime().block_on(async {
+            watch_devices(Duration::from_millis(1), query)
+                .take(5)
+                .collect::<Vec<_>>()
+                .await
+        });
+        let paths = paths(&events);
+        assert_eq!(paths[..2], ["+1-1", "!Malformed query result: Test"]);
+        assert!(paths[2].starts_with("!Querying devices panicked"));
+        // Devices from before the errors are still known.
+        assert_eq!(paths[3..], ["-1-1", "+1-2"]);
+    }
+
+    #[cfg(feature = "input")]
+    #[test]
+    fn event_stream() {
+        use std::{
+            future::poll_fn,
+            io::{ErrorKind, Read, Write},
+            os::unix::net::UnixStream,
+        };
+
+        use tokio::io::unix::AsyncFd;
+
+        use super::input::poll_next_event;
+        use crate::{EventType, InputEvent};
+
+        // Each byte is a key press, closing the socket is an error like unplugging the device.
+        fn try_next(socket: &mut UnixStream) -> Result<Option<InputEvent>, std::io::Error> {
+            let mut byte = [0];
+            match socket.read(&mut byte) {
+                Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+                Ok(_) => Ok(Some(InputEvent::new(EventType::KEY.0, byte[0].into(), 1))),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(None),
+                Err(err) => Err(err),
+            }
+        }
+
+        runtime().block_on(async {
+            let (mut writer, reader) = UnixStream::pair().unwrap();
+            reader.set_nonblocking(true).unwrap();
+            let mut device = Some(AsyncFd::new(reader).unwrap());
+            let mut next = async || poll_fn(|cx| poll_next_event(&mut device, cx, try_next)).await;
+
+            writer.write_all(&[30, 48]).unwrap();
+            assert_eq!(next().await.unwrap().unwrap().code(), 30);
+            assert_eq!(next().await.unwrap().unwrap().code(), 48);
+
+            // Waits for the device to be readable again.
+            let write = tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                writer.write_all(&[46]).unwrap();
+                writer
+            });
+            assert_eq!(next().await.unwrap().unwrap().code(), 46);
+
+            drop(write.await.unwrap());
+            assert!(next().await.unwrap().is_err());
+            assert!(next().await.is_none());
+        });
+    }
+}
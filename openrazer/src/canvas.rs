@@ -0,0 +1,96 @@
+use crate::Color;
+
+/// Grid of colors that isn't tied to a device, effects draw into this before it is sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Canvas {
+    width: usize,
+    height: usize,
+    colors: Vec<Color>,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            colors: vec![Color::new(0.0, 0.0, 0.0); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Change the size, the canvas is cleared if the size changed.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        if width != self.width || height != self.height {
+            *self = Self::new(width, height);
+        }
+    }
+
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(x + y * self.width)
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<&Color> {
+        self.colors.get(self.index(x, y)?)
+    }
+
+    pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut Color> {
+        let index = self.index(x, y)?;
+        self.colors.get_mut(index)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize, &Color)> {
+        let width = self.width;
+        self.colors
+            .iter()
+            .enumerate()
+            .map(move |(i, c)| (i % width, i / width, c))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, usize, &mut Color)> {
+        let width = self.width;
+        self.colors
+            .iter_mut()
+            .enumerate()
+            .map(move |(i, c)| (i % width, i / width, c))
+    }
+
+    pub fn fill(&mut self, color: Color) {
+        self.colors.fill(color);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Canvas;
+    use crate::Color;
+
+    #[test]
+    fn canvas() {
+        let mut canvas = Canvas::new(3, 2);
+        *canvas.get_mut(2, 1).unwrap() = Color::new(1.0, 0.0, 0.0);
+        assert!(canvas.get(3, 0).is_none());
+        assert_eq!(
+            canvas
+                .iter()
+                .find(|(_, _, c)| c.r == 1.0)
+                .map(|(x, y, _)| (x, y)),
+            Some((2, 1))
+        );
+
+        canvas.resize(3, 2);
+        assert_eq!(canvas.get(2, 1), Some(&Color::new(1.0, 0.0, 0.0)));
+        canvas.resize(4, 4);
+        assert_eq!(canvas.iter().count(), 16);
+        assert_eq!(canvas.get(2, 1), Some(&Color::new(0.0, 0.0, 0.0)));
+    }
+}
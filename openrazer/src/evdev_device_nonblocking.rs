@@ -1,36 +1,401 @@
 use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, RawFd},
+        unix::fs::OpenOptionsExt,
+    },
     path::Path,
-    sync::mpsc::{Receiver, TryRecvError},
+    time::Duration,
 };
 
-// FIXME: This definitely doesn't drop correctly.
-// The thread keeps running for the whole program, and when the program exits this goes as well.
+use evdev::{
+    raw_stream::RawDevice, AttributeSet, EventSummary, EventType, InputEvent, KeyCode, LedCode,
+    SynchronizationCode,
+};
+
+/// Key & LED state followed through events, filling in what a `SYN_DROPPED` lost.
+///
+/// Doesn't touch the device, so the resync can be tested without one.
+#[derive(Debug, Default)]
+struct EventSync {
+    /// Keys that are currently held, according to the events we have seen.
+    key_state: AttributeSet<KeyCode>,
+    /// LEDs that are currently on (e.g. Caps Lock), according to the events we have seen.
+    led_state: AttributeSet<LedCode>,
+    /// Events were dropped by the kernel, everything up to the next `SYN_REPORT` is incomplete.
+    dropped: bool,
+}
+
+impl EventSync {
+    /// Handle an event read from the device, events to pass on are pushed to `out`.
+    ///
+    /// Returns if the real state has to be read from the device & given to [`EventSync::resync`].
+    fn process(&mut self, event: InputEvent, out: &mut VecDeque<InputEvent>) -> bool {
+        match event.destructure() {
+            EventSummary::Synchronization(_, SynchronizationCode::SYN_DROPPED, _) => {
+                log::warn!("Evdev device dropped events, resyncing");
+                self.dropped = true;
+            }
+            EventSummary::Synchronization(_, SynchronizationCode::SYN_REPORT, _)
+                if self.dropped =>
+            {
+                self.dropped = false;
+                return true;
+            }
+            _ if self.dropped => {}
+            EventSummary::Key(_, key, value) => {
+                match value {
+                    0 => self.key_state.remove(key),
+                    1 => self.key_state.insert(key),
+                    _ => {}
+                }
+                out.push_back(event);
+            }
+            EventSummary::Led(_, led, value) => {
+                if value == 0 {
+                    self.led_state.remove(led);
+                } else {
+                    self.led_state.insert(led);
+                }
+                out.push_back(event);
+            }
+            _ => out.push_back(event),
+        }
+        false
+    }
+
+    /// Handle events read from the device at once, `state` reads the real key & LED state for a
+    /// resync.
+    ///
+    /// The state is read after the whole batch, so it already includes the events after the
+    /// `SYN_REPORT` that ends a drop. Those are dropped instead of being replayed after the resync.
+    fn process_batch(
+        &mut self,
+        events: impl IntoIterator<Item = InputEvent>,
+        out: &mut VecDeque<InputEvent>,
+        state: impl FnOnce() -> std::io::Result<(AttributeSet<KeyCode>, AttributeSet<LedCode>)>,
+    ) -> Result<(), std::io::Error> {
+        for event in events {
+            if self.process(event, out) {
+                let (key_state, led_state) = state()?;
+                self.resync(key_state, led_state, out);
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Emit events for everything that differs from the real state, & take it as ours.
+    fn resync(
+        &mut self,
+        key_state: AttributeSet<KeyCode>,
+        led_state: AttributeSet<LedCode>,
+        out: &mut VecDeque<InputEvent>,
+    ) {
+        let released = self
+            .key_state
+            .iter()
+            .filter(|key| !key_state.contains(*key));
+        let pressed = key_state
+            .iter()
+            .filter(|key| !self.key_state.contains(*key));
+        let mut events = released
+            .map(|key| (key, 0))
+            .chain(pressed.map(|key| (key, 1)))
+            .map(|(key, value)| InputEvent::new_now(EventType::KEY.0, key.code(), value))
+            .collect::<Vec<_>>();
+
+        let off = self
+            .led_state
+            .iter()
+            .filter(|led| !led_state.contains(*led));
+        let on = led_state
+            .iter()
+            .filter(|led| !self.led_state.contains(*led));
+        events.extend(
+            off.map(|led| (led, 0))
+                .chain(on.map(|led| (led, 1)))
+                .map(|(led, value)| InputEvent::new_now(EventType::LED.0, led.0, value)),
+        );
 
-/// [`evdev::Device::set_nonblocking`] doesn't work, so this is just a workaround for that.
+        if !events.is_empty() {
+            out.extend(events);
+            out.push_back(InputEvent::new_now(
+                EventType::SYNCHRONIZATION.0,
+                SynchronizationCode::SYN_REPORT.0,
+                0,
+            ));
+        }
+        self.key_state = key_state;
+        self.led_state = led_state;
+    }
+}
+
+/// Reads events from an evdev device without blocking.
+///
+/// [`evdev::Device::set_nonblocking`] loses the events that compensate for a `SYN_DROPPED` if
+/// there is nothing to read afterwards, so this does the resync itself on a [`RawDevice`].
+///
+/// Nothing runs in the background, dropping this closes the device.
 #[derive(Debug)]
 pub struct EvdevDeviceNonblocking {
-    rx: Receiver<evdev::InputEvent>,
+    device: RawDevice,
+    pending: VecDeque<InputEvent>,
+    sync: EventSync,
 }
 
 impl EvdevDeviceNonblocking {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
-        let (tx, rx) = std::sync::mpsc::channel();
-        std::thread::spawn({
-            let mut device = evdev::Device::open(&path)?;
-            move || loop {
-                for event in device.fetch_events().unwrap() {
-                    tx.send(event).unwrap();
+        // Same as [`RawDevice::open`], write access is only needed for things like LEDs.
+        let file = std::fs::File::options()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+            .or_else(|_| {
+                std::fs::File::options()
+                    .read(true)
+                    .custom_flags(libc::O_NONBLOCK)
+                    .open(&path)
+            })?;
+        let device = RawDevice::try_from(file)?;
+        let sync = EventSync {
+            key_state: device.get_key_state()?,
+            led_state: device.get_led_state()?,
+            dropped: false,
+        };
+        Ok(Self {
+            device,
+            pending: VecDeque::new(),
+            sync,
+        })
+    }
+
+    pub fn device(&self) -> &RawDevice {
+        &self.device
+    }
+
+    /// LEDs that are on, as of the last event read.
+    pub fn led_state(&self) -> &AttributeSet<LedCode> {
+        &self.sync.led_state
+    }
+
+    /// Read what the kernel has buffered into `pending`.
+    ///
+    /// Reads until there is an event to pass on or reading would block, as a read can have only
+    /// events that are dropped, e.g. after a `SYN_DROPPED`.
+    fn fill(&mut self) -> Result<(), std::io::Error> {
+        while self.pending.is_empty() {
+            let events = match self.device.fetch_events() {
+                Ok(events) => events.collect::<Vec<_>>(),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            };
+
+            let device = &self.device;
+            self.sync.process_batch(events, &mut self.pending, || {
+                Ok((device.get_key_state()?, device.get_led_state()?))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Get the next event if there is one, [`None`] means reading from the device would block.
+    ///
+    /// Errors if reading from the device failed, e.g. if it was unplugged.
+    pub fn try_next(&mut self) -> Result<Option<InputEvent>, std::io::Error> {
+        if self.pending.is_empty() {
+            self.fill()?;
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    /// Block until there is an event to read or the timeout passes.
+    ///
+    /// Returns if there is an event to read, waits forever if `timeout` is [`None`].
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Result<bool, std::io::Error> {
+        Self::wait_any(&mut [self], timeout)
+    }
+
+    /// Block until any of the devices has an event to read or the timeout passes, e.g. for a
+    /// keyboard that has its LEDs on another device.
+    ///
+    /// Returns if there is an event to read, waits forever if `timeout` is [`None`]. Being
+    /// interrupted by a signal counts as the timeout passing.
+    pub fn wait_any(
+        devices: &mut [&mut Self],
+        timeout: Option<Duration>,
+    ) -> Result<bool, std::io::Error> {
+        if devices.iter().any(|device| !device.pending.is_empty()) {
+            return Ok(true);
+        }
+
+        let mut pollfds = devices
+            .iter()
+            .map(|device| libc::pollfd {
+                fd: device.device.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect::<Vec<_>>();
+        // Rounded up, so we don't wake up just before the timeout & spin.
+        let timeout = timeout
+            .map(|timeout| timeout.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32)
+            .unwrap_or(-1);
+
+        // SAFETY: `pollfds` is a live Vec for the whole call & `poll` only writes `revents` within
+        // its `len` entries. The fds are borrowed from `devices`, which stay open until we return.
+        // `nfds_t` is an unsigned long, so the length always fits.
+        match unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout) } {
+            -1 => {
+                let err = std::io::Error::last_os_error();
+                if err.kind() == ErrorKind::Interrupted {
+                    return Ok(false);
                 }
+                Err(err)
             }
-        });
-        Ok(Self { rx })
+            0 => Ok(false),
+            _ => {
+                let mut ready = false;
+                for (device, pollfd) in devices.iter_mut().zip(pollfds) {
+                    if pollfd.revents != 0 {
+                        // POLLERR & POLLHUP are reported by the read.
+                        device.fill()?;
+                        ready |= !device.pending.is_empty();
+                    }
+                }
+                Ok(ready)
+            }
+        }
     }
+}
 
-    pub fn try_next(&self) -> Result<Option<evdev::InputEvent>, TryRecvError> {
-        match self.rx.try_recv() {
-            Ok(event) => Ok(Some(event)),
-            Err(TryRecvError::Empty) => Ok(None),
-            Err(err) => Err(err),
-        }
+impl AsFd for EvdevDeviceNonblocking {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.device.as_fd()
+    }
+}
+
+impl AsRawFd for EvdevDeviceNonblocking {
+    fn as_raw_fd(&self) -> RawFd {
+        self.device.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use evdev::{AttributeSet, EventType, InputEvent, KeyCode, LedCode, SynchronizationCode};
+
+    use super::EventSync;
+
+    fn key(key: KeyCode, value: i32) -> InputEvent {
+        InputEvent::new(EventType::KEY.0, key.code(), value)
+    }
+
+    fn syn(code: SynchronizationCode) -> InputEvent {
+        InputEvent::new(EventType::SYNCHRONIZATION.0, code.0, 0)
+    }
+
+    fn drain(events: &mut VecDeque<InputEvent>) -> Vec<(EventType, u16, i32)> {
+        events
+            .drain(..)
+            .map(|event| (event.event_type(), event.code(), event.value()))
+            .collect()
+    }
+
+    #[test]
+    fn resync_after_dropped() {
+        let mut sync = EventSync::default();
+        let mut out = VecDeque::new();
+
+        assert!(!sync.process(key(KeyCode::KEY_A, 1), &mut out));
+        assert!(!sync.process(syn(SynchronizationCode::SYN_REPORT), &mut out));
+        assert_eq!(out.len(), 2);
+        out.clear();
+
+        // Everything up to the next report is incomplete & dropped.
+        assert!(!sync.process(syn(SynchronizationCode::SYN_DROPPED), &mut out));
+        assert!(!sync.process(key(KeyCode::KEY_A, 0), &mut out));
+        assert!(!sync.process(key(KeyCode::KEY_B, 1), &mut out));
+        assert!(out.is_empty());
+        assert!(sync.process(syn(SynchronizationCode::SYN_REPORT), &mut out));
+
+        // A was released & B pressed while dropped, & Caps Lock turned on.
+        let mut keys = AttributeSet::new();
+        keys.insert(KeyCode::KEY_B);
+        let mut leds = AttributeSet::new();
+        leds.insert(LedCode::LED_CAPSL);
+        sync.resync(keys, leds, &mut out);
+        assert_eq!(
+            drain(&mut out),
+            [
+                (EventType::KEY, KeyCode::KEY_A.code(), 0),
+                (EventType::KEY, KeyCode::KEY_B.code(), 1),
+                (EventType::LED, LedCode::LED_CAPSL.0, 1),
+                (
+                    EventType::SYNCHRONIZATION,
+                    SynchronizationCode::SYN_REPORT.0,
+                    0
+                ),
+            ]
+        );
+        assert!(sync.key_state.contains(KeyCode::KEY_B));
+        assert!(!sync.key_state.contains(KeyCode::KEY_A));
+
+        // Nothing changed, so nothing is emitted.
+        sync.resync(sync.key_state.clone(), sync.led_state.clone(), &mut out);
+        assert!(out.is_empty());
+
+        // Events pass through again after the resync.
+        assert!(!sync.process(key(KeyCode::KEY_B, 0), &mut out));
+        assert_eq!(out.len(), 1);
+        assert!(!sync.key_state.contains(KeyCode::KEY_B));
+    }
+
+    #[test]
+    fn resync_in_batch() {
+        let mut sync = EventSync::default();
+        sync.key_state.insert(KeyCode::KEY_A);
+        let mut out = VecDeque::new();
+
+        // The state is read after B was pressed, so its events aren't replayed after the resync.
+        let mut keys = AttributeSet::new();
+        keys.insert(KeyCode::KEY_B);
+        sync.process_batch(
+            [
+                syn(SynchronizationCode::SYN_DROPPED),
+                key(KeyCode::KEY_A, 0),
+                syn(SynchronizationCode::SYN_REPORT),
+                key(KeyCode::KEY_B, 1),
+                syn(SynchronizationCode::SYN_REPORT),
+            ],
+            &mut out,
+            || Ok((keys, AttributeSet::new())),
+        )
+        .unwrap();
+        assert_eq!(
+            drain(&mut out),
+            [
+                (EventType::KEY, KeyCode::KEY_A.code(), 0),
+                (EventType::KEY, KeyCode::KEY_B.code(), 1),
+                (
+                    EventType::SYNCHRONIZATION,
+                    SynchronizationCode::SYN_REPORT.0,
+                    0
+                ),
+            ]
+        );
+
+        // Batches without a drop pass through.
+        sync.process_batch(
+            [key(KeyCode::KEY_B, 0), syn(SynchronizationCode::SYN_REPORT)],
+            &mut out,
+            || panic!("No resync without a drop"),
+        )
+        .unwrap();
+        assert_eq!(out.len(), 2);
     }
 }
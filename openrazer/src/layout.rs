@@ -0,0 +1,393 @@
+//! Keyboard layout files, mapping keys to matrix cells.
+//!
+//! Layouts are TOML (or JSON) files, see `layouts/` for the built in ones.
+
+use std::{collections::BTreeMap, fmt, path::Path, str::FromStr};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+use crate::{KeyCode, MatrixMapper};
+
+/// Size of a standard key in millimetres, positions in [`MatrixMapper`] are in key units.
+pub const KEY_UNIT_MM: f64 = 19.05;
+
+static BUILTIN_LAYOUTS: &[&str] = &[include_str!("../layouts/ornata_chroma.toml")];
+
+#[derive(Debug, Error)]
+pub enum LayoutError {
+    #[error("Failed to parse layout: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("Failed to parse layout: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Failed to write layout: {0}")]
+    TomlSerialize(#[from] toml::ser::Error),
+    #[error("Layout has no variant {0:?}")]
+    UnknownVariant(String),
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// Key in a layout file, either the evdev name (`KEY_ESC`) or the raw key code (`"685"`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LayoutKey(pub KeyCode);
+
+impl fmt::Display for LayoutKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Unknown keys don't have a name that can be parsed back.
+        let name = format!("{:?}", self.0);
+        if KeyCode::from_str(&name).is_ok_and(|key| key == self.0) {
+            write!(f, "{name}")
+        } else {
+            write!(f, "{}", self.0.code())
+        }
+    }
+}
+
+impl FromStr for LayoutKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(code) = s.parse::<u16>() {
+            return Ok(Self(KeyCode(code)));
+        }
+        KeyCode::from_str(s)
+            .map(Self)
+            .map_err(|_| format!("Unknown key {s:?}"))
+    }
+}
+
+impl Serialize for LayoutKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for LayoutKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Cells of a key, either `[x, y]` or `[[x, y], ...]` for keys with multiple LEDs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LayoutCells(pub Vec<(usize, usize)>);
+
+impl From<(usize, usize)> for LayoutCells {
+    fn from(pos: (usize, usize)) -> Self {
+        Self(vec![pos])
+    }
+}
+
+impl Serialize for LayoutCells {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0.as_slice() {
+            [pos] => pos.serialize(serializer),
+            cells => cells.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for LayoutCells {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Cells {
+            One((usize, usize)),
+            Many(Vec<(usize, usize)>),
+        }
+
+        Ok(match Cells::deserialize(deserializer)? {
+            Cells::One(pos) => Self(vec![pos]),
+            Cells::Many(cells) => Self(cells),
+        })
+    }
+}
+
+/// Physical outline of a key in millimetres, from the top left of the keyboard.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeyRect {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+}
+
+impl KeyRect {
+    /// Split into `count` equal parts from left to right, one for each LED.
+    pub fn split(&self, count: usize) -> impl Iterator<Item = KeyRect> + '_ {
+        (0..count).map(move |i| KeyRect {
+            x: self.x + self.w * i as f64 / count as f64,
+            w: self.w / count as f64,
+            ..*self
+        })
+    }
+
+    /// Centres of `count` LEDs spread evenly from left to right over the key.
+    pub fn led_centers(&self, count: usize) -> impl Iterator<Item = (f64, f64)> + '_ {
+        (0..count).map(move |i| {
+            (
+                self.x + self.w * (i as f64 + 0.5) / count as f64,
+                self.y + self.h / 2.0,
+            )
+        })
+    }
+}
+
+/// Changes to the base layout, e.g. for ISO boards.
+///
+/// Keys in a variant replace all cells of the key in the base layout.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LayoutVariant {
+    #[serde(default)]
+    pub keys: BTreeMap<LayoutKey, LayoutCells>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub geometry: BTreeMap<LayoutKey, KeyRect>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Layout {
+    pub name: String,
+    /// USB product IDs this layout is for.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub products: Vec<u16>,
+    #[serde(default)]
+    pub keys: BTreeMap<LayoutKey, LayoutCells>,
+    /// Optional key outlines, LEDs are placed evenly over a key's cells from left to right.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub geometry: BTreeMap<LayoutKey, KeyRect>,
+    /// Named groups of keys, e.g. `wasd = ["KEY_W", "KEY_A", "KEY_S", "KEY_D"]`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, Vec<LayoutKey>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variants: BTreeMap<String, LayoutVariant>,
+}
+
+impl Layout {
+    pub fn from_toml(str: &str) -> Result<Self, LayoutError> {
+        Ok(toml::from_str(str)?)
+    }
+
+    pub fn from_json(str: &str) -> Result<Self, LayoutError> {
+        Ok(serde_json::from_str(str)?)
+    }
+
+    /// Load a layout file, `.json` files are JSON and everything else is TOML.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LayoutError> {
+        let str = std::fs::read_to_string(&path)?;
+        if path.as_ref().extension().is_some_and(|ext| ext == "json") {
+            Self::from_json(&str)
+        } else {
+            Self::from_toml(&str)
+        }
+    }
+
+    pub fn to_toml(&self) -> Result<String, LayoutError> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    /// Save a layout file, `.json` files are JSON and everything else is TOML.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), LayoutError> {
+        let str = if path.as_ref().extension().is_some_and(|ext| ext == "json") {
+            serde_json::to_string_pretty(self)?
+        } else {
+            self.to_toml()?
+        };
+        std::fs::write(path, str)?;
+        Ok(())
+    }
+
+    /// Built in layout for a USB product ID.
+    pub fn builtin(id_product: u16) -> Option<Self> {
+        Self::builtins().find(|layout| layout.products.contains(&id_product))
+    }
+
+    pub fn builtins() -> impl Iterator<Item = Self> {
+        BUILTIN_LAYOUTS
+            .iter()
+            .map(|str| Self::from_toml(str).expect("Built in layout is invalid"))
+    }
+
+    fn variant(&self, variant: Option<&str>) -> Result<Option<&LayoutVariant>, LayoutError> {
+        variant
+            .map(|name| {
+                self.variants
+                    .get(name)
+                    .ok_or_else(|| LayoutError::UnknownVariant(name.to_owned()))
+            })
+            .transpose()
+    }
+
+    /// Key mappings with a variant applied.
+    pub fn keys(
+        &self,
+        variant: Option<&str>,
+    ) -> Result<BTreeMap<LayoutKey, LayoutCells>, LayoutError> {
+        let mut keys = self.keys.clone();
+        if let Some(variant) = self.variant(variant)? {
+            keys.extend(
+                variant
+                    .keys
+                    .iter()
+                    .map(|(key, cells)| (*key, cells.clone())),
+            );
+        }
+        Ok(keys)
+    }
+
+    /// Key outlines with a variant applied.
+    pub fn geometry(
+        &self,
+        variant: Option<&str>,
+    ) -> Result<BTreeMap<LayoutKey, KeyRect>, LayoutError> {
+        let mut geometry = self.geometry.clone();
+        if let Some(variant) = self.variant(variant)? {
+            geometry.extend(variant.geometry.iter().map(|(key, rect)| (*key, *rect)));
+        }
+        Ok(geometry)
+    }
+}
+
+impl MatrixMapper {
+    pub fn from_layout(layout: &Layout, variant: Option<&str>) -> Result<Self, LayoutError> {
+        let mut mapper = MatrixMapper::default();
+        let keys = layout.keys(variant)?;
+        mapper.add_mappings(
+            keys.iter()
+                .flat_map(|(LayoutKey(key), cells)| cells.0.iter().map(move |pos| (*key, *pos))),
+        );
+
+        for (key, rect) in layout.geometry(variant)? {
+            let Some(cells) = keys.get(&key) else {
+                log::warn!("Layout has geometry for unmapped key {key}");
+                continue;
+            };
+            let mut cells = cells.0.clone();
+            cells.sort();
+            let centers = rect.led_centers(cells.len());
+            for ((x, y), (px, py)) in cells.into_iter().zip(centers) {
+                mapper.set_position(x, y, ((px / KEY_UNIT_MM) as f32, (py / KEY_UNIT_MM) as f32));
+            }
+        }
+
+        for (name, keys) in layout.groups.iter() {
+            mapper.set_group(name, keys.iter().map(|LayoutKey(key)| *key));
+        }
+        Ok(mapper)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::{Layout, LayoutKey};
+    use crate::{KeyCode, MatrixMapper};
+
+    #[test]
+    fn builtin_layouts() {
+        let layout = Layout::builtin(0x021E).unwrap();
+        let mapper = MatrixMapper::from_layout(&layout, None).unwrap();
+        assert_eq!(mapper.map(KeyCode::KEY_ESC), Some((1, 0)));
+        assert_eq!(mapper.map(KeyCode(685)), Some((11, 0)));
+        assert_eq!(mapper.map(KeyCode::KEY_102ND), None);
+        assert_eq!(mapper.cells(KeyCode::KEY_SPACE).len(), 7);
+        assert_eq!(mapper.keys_at(3, 0), &[KeyCode::KEY_F1, KeyCode::KEY_MUTE]);
+
+        let mapper = MatrixMapper::from_layout(&layout, Some("iso")).unwrap();
+        assert_eq!(mapper.map(KeyCode::KEY_102ND), Some((2, 4)));
+        assert_eq!(mapper.cells(KeyCode::KEY_BACKSLASH), &[(13, 3)]);
+        let (x, y) = mapper.position(13, 3);
+        assert!((x - 13.25).abs() < 0.01 && (y - 4.0).abs() < 0.01);
+        assert!(MatrixMapper::from_layout(&layout, Some("jis")).is_err());
+        assert_eq!(mapper.group_cells("wasd").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn groups() {
+        let layout = Layout::from_toml(
+            r#"
+            name = "Test"
+
+            [keys]
+            KEY_A = [1, 2]
+            KEY_S = [2, 2]
+            KEY_W = [2, 1]
+            KEY_SPACE = [[4, 5], [5, 5]]
+
+            [groups]
+            wasd = ["KEY_W", "KEY_A", "KEY_S", "KEY_D"]
+            both = ["KEY_SPACE", "KEY_BACKSLASH", "KEY_SPACE"]
+
+            [variants.iso.keys]
+            KEY_BACKSLASH = [3, 3]
+            "#,
+        )
+        .unwrap();
+
+        // Keys without cells are skipped, cells are in row order without duplicates.
+        let mapper = MatrixMapper::from_layout(&layout, None).unwrap();
+        assert_eq!(
+            mapper.group("wasd").unwrap(),
+            [
+                KeyCode::KEY_W,
+                KeyCode::KEY_A,
+                KeyCode::KEY_S,
+                KeyCode::KEY_D
+            ]
+        );
+        assert_eq!(
+            mapper.group_cells("wasd"),
+            Some(vec![(2, 1), (1, 2), (2, 2)])
+        );
+        assert_eq!(mapper.group_cells("both"), Some(vec![(4, 5), (5, 5)]));
+        assert_eq!(
+            mapper.groups().map(|(name, _)| name).collect::<Vec<_>>(),
+            ["both", "wasd"]
+        );
+
+        // Groups follow the variant's mapping.
+        let mut mapper = MatrixMapper::from_layout(&layout, Some("iso")).unwrap();
+        assert_eq!(
+            mapper.group_cells("both"),
+            Some(vec![(3, 3), (4, 5), (5, 5)])
+        );
+
+        // Groups from the config replace the layout's.
+        mapper.set_group("wasd", [KeyCode::KEY_SPACE]);
+        assert_eq!(mapper.group_cells("wasd"), Some(vec![(4, 5), (5, 5)]));
+        assert_eq!(mapper.group_cells("arrows"), None);
+    }
+
+    #[test]
+    fn builtin_groups() {
+        for layout in Layout::builtins() {
+            let mapper = MatrixMapper::from_layout(&layout, None).unwrap();
+            for (name, keys) in mapper.groups() {
+                let cells = mapper.group_cells(name).unwrap();
+                assert!(!cells.is_empty(), "{} group {name} is empty", layout.name);
+                for key in keys {
+                    assert!(
+                        !mapper.cells(*key).is_empty(),
+                        "{} group {name} has unmapped {key:?}",
+                        layout.name
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        assert_eq!(LayoutKey::from_str("KEY_A").unwrap().0, KeyCode::KEY_A);
+        assert_eq!(LayoutKey(KeyCode(685)).to_string(), "685");
+
+        let layout = Layout::builtin(0x021E).unwrap();
+        assert_eq!(
+            Layout::from_toml(&layout.to_toml().unwrap()).unwrap(),
+            layout
+        );
+    }
+}
@@ -1,15 +1,28 @@
+#[cfg(feature = "tokio")]
+mod async_api;
+mod canvas;
 mod color;
+#[cfg(feature = "input")]
 mod evdev_device_nonblocking;
+#[cfg(feature = "input")]
+mod layout;
 mod query;
 mod razer;
 
+#[cfg(feature = "tokio")]
+pub use async_api::*;
+pub use canvas::*;
 pub use color::*;
+#[cfg(feature = "input")]
 pub use evdev_device_nonblocking::*;
+#[cfg(feature = "input")]
+pub use layout::*;
 pub use query::*;
 pub use razer::*;
 
+#[cfg(feature = "input")]
 pub use evdev::{
     AbsoluteAxisEvent, EventSummary, EventType, FFStatusEvent, InputEvent, KeyCode, KeyEvent,
-    LedEvent, MiscEvent, OtherEvent, PowerEvent, RelativeAxisEvent, RepeatEvent, SoundEvent,
-    SwitchEvent, SynchronizationEvent, UInputEvent,
+    LedCode, LedEvent, MiscEvent, OtherEvent, PowerEvent, RelativeAxisEvent, RepeatEvent,
+    SoundEvent, SwitchEvent, SynchronizationEvent, UInputEvent,
 };
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use regex::Regex;
 
-use super::QueryError;
+use super::{parse_bitmap, QueryError};
 
 // https://unix.stackexchange.com/questions/74903/explain-ev-in-proc-bus-input-devices-data
 
@@ -146,14 +146,7 @@ impl QueryDevice {
                     if bitmaps.contains_key(pname) {
                         return Err(QueryError::Malformed("Duplicate bits field"));
                     }
-                    bitmaps.insert(
-                        pname.to_owned(),
-                        pbits
-                            .split_whitespace()
-                            .map(|b| u64::from_str_radix(b, 16))
-                            .collect::<Result<_, _>>()
-                            .map_err(|_| QueryError::Malformed("Malformed bits field"))?,
-                    );
+                    bitmaps.insert(pname.to_owned(), parse_bitmap(pbits)?);
                     Ok(None)
                 }
                 _ => Ok(Some(prop)),
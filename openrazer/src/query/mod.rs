@@ -1,8 +1,10 @@
 //! Query devices
 
 mod devices;
+mod sysfs;
 
 pub use devices::*;
+pub use sysfs::*;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -12,3 +14,38 @@ pub enum QueryError {
     #[error(transparent)]
     IoError(#[from] std::io::Error),
 }
+
+/// Parse a whitespace separated list of hex words, most significant word first.
+fn parse_bitmap(str: &str) -> Result<Box<[u64]>, QueryError> {
+    str.split_whitespace()
+        .map(|b| u64::from_str_radix(b, 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| QueryError::Malformed("Malformed bits field"))
+}
+
+/// Check if a bit is set in a bitmap of hex words, most significant word first (e.g.
+/// [`SysfsInputDevice::capabilities`]).
+pub fn bitmap_contains(bitmap: &[u64], bit: usize) -> bool {
+    bitmap
+        .iter()
+        .rev()
+        .nth(bit / 64)
+        .is_some_and(|word| word & (1 << (bit % 64)) != 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bitmap_contains, parse_bitmap};
+
+    #[test]
+    fn bitmap() {
+        let bitmap = parse_bitmap("3 120013").unwrap();
+        assert!(bitmap_contains(&bitmap, 0x00));
+        assert!(bitmap_contains(&bitmap, 0x01));
+        assert!(bitmap_contains(&bitmap, 0x11));
+        assert!(!bitmap_contains(&bitmap, 0x02));
+        assert!(bitmap_contains(&bitmap, 64));
+        assert!(bitmap_contains(&bitmap, 65));
+        assert!(!bitmap_contains(&bitmap, 128));
+    }
+}
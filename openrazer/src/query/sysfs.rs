@@ -0,0 +1,387 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use super::{parse_bitmap, QueryError};
+
+// https://www.kernel.org/doc/Documentation/ABI/stable/sysfs-bus-usb
+// https://www.kernel.org/doc/Documentation/ABI/testing/sysfs-class-input
+
+const SYSFS_ROOT: &str = "/sys";
+const SYSFS_USB_DEVICES: &str = "bus/usb/devices";
+const SYSFS_INPUT_CLASS: &str = "class/input";
+
+/// Input device (`/sys/class/input/inputN`) belonging to a HID device.
+#[derive(Debug, Clone)]
+pub struct SysfsInputDevice {
+    pub sys_path: PathBuf,
+    pub name: String,
+    /// Path of the evdev node, e.g. `/dev/input/event5`
+    pub event_path: Option<PathBuf>,
+    /// Contents of the `capabilities` directory, same format as the `B:` lines of
+    /// `/proc/bus/input/devices`.
+    pub capabilities: HashMap<String, Box<[u64]>>,
+}
+
+/// HID device bound below a USB interface, this is where drivers (e.g. OpenRazer) put their
+/// attributes.
+#[derive(Debug, Clone)]
+pub struct SysfsHidDevice {
+    pub sys_path: PathBuf,
+    pub driver: Option<String>,
+    pub inputs: Box<[SysfsInputDevice]>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SysfsUsbInterface {
+    pub sys_path: PathBuf,
+    pub number: u8,
+    pub driver: Option<String>,
+    pub hid_devices: Box<[SysfsHidDevice]>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SysfsUsbDevice {
+    pub sys_path: PathBuf,
+    pub id_vendor: u16,
+    pub id_product: u16,
+    pub serial: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub interfaces: Box<[SysfsUsbInterface]>,
+}
+
+fn read_attribute(path: &Path, attribute: &str) -> Option<String> {
+    std::fs::read_to_string(path.join(attribute))
+        .ok()
+        .map(|s| s.trim().to_owned())
+}
+
+fn read_hex_attribute(path: &Path, attribute: &str) -> Result<u16, QueryError> {
+    let Some(value) = read_attribute(path, attribute) else {
+        return Err(QueryError::Malformed("Missing sysfs attribute"));
+    };
+    u16::from_str_radix(&value, 16).map_err(|_| QueryError::Malformed("Malformed sysfs attribute"))
+}
+
+fn read_driver(path: &Path) -> Option<String> {
+    std::fs::read_link(path.join("driver"))
+        .ok()
+        .and_then(|driver| Some(driver.file_name()?.to_str()?.to_owned()))
+}
+
+fn read_capabilities(path: &Path) -> Result<HashMap<String, Box<[u64]>>, QueryError> {
+    let mut capabilities = HashMap::new();
+    let Ok(entries) = std::fs::read_dir(path.join("capabilities")) else {
+        return Ok(capabilities);
+    };
+    for entry in entries.flat_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().to_uppercase();
+        let content = std::fs::read_to_string(entry.path())?;
+        capabilities.insert(name, parse_bitmap(&content)?);
+    }
+    Ok(capabilities)
+}
+
+/// HID device directories are named `BUS:VENDOR:PRODUCT.INSTANCE`, e.g. `0003:1532:021E.0008`
+fn is_hid_device_name(name: &str) -> bool {
+    let Some((ids, instance)) = name.split_once('.') else {
+        return false;
+    };
+    let ids = ids.split(':').collect::<Vec<_>>();
+    ids.len() == 3
+        && ids
+            .iter()
+            .chain(std::iter::once(&instance))
+            .all(|s| !s.is_empty() && s.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+fn query_input_device(path: &Path) -> Result<SysfsInputDevice, QueryError> {
+    let sys_path = path.canonicalize()?;
+    let event_path = std::fs::read_dir(&sys_path)?
+        .flat_map(|entry| entry.ok())
+        .find(|entry| entry.file_name().to_string_lossy().starts_with("event"))
+        .map(|entry| Path::new("/dev/input/").join(entry.file_name()));
+    Ok(SysfsInputDevice {
+        name: read_attribute(&sys_path, "name").unwrap_or_default(),
+        event_path,
+        capabilities: read_capabilities(&sys_path)?,
+        sys_path,
+    })
+}
+
+/// All input devices, with their canonical sysfs path so they can be matched to their parents.
+///
+/// Devices that can't be read (e.g. unplugged while reading) are skipped, they can't stop other
+/// devices from being found.
+fn query_input_devices(root: &Path) -> Result<Vec<SysfsInputDevice>, QueryError> {
+    let mut inputs = Vec::new();
+    for entry in std::fs::read_dir(root.join(SYSFS_INPUT_CLASS))?.flat_map(|entry| entry.ok()) {
+        if !entry.file_name().to_string_lossy().starts_with("input") {
+            continue;
+        }
+        match query_input_device(&entry.path()) {
+            Ok(input) => inputs.push(input),
+            Err(err) => log::debug!("Skipping input device {:?}: {err}", entry.path()),
+        }
+    }
+    Ok(inputs)
+}
+
+fn query_hid_devices(
+    interface_path: &Path,
+    inputs: &[SysfsInputDevice],
+) -> Result<Box<[SysfsHidDevice]>, QueryError> {
+    std::fs::read_dir(interface_path)?
+        .flat_map(|entry| entry.ok())
+        .filter(|entry| is_hid_device_name(&entry.file_name().to_string_lossy()))
+        .map(|entry| {
+            let sys_path = entry.path().canonicalize()?;
+            Ok(SysfsHidDevice {
+                driver: read_driver(&sys_path),
+                inputs: inputs
+                    .iter()
+                    .filter(|input| input.sys_path.starts_with(&sys_path))
+                    .cloned()
+                    .collect(),
+                sys_path,
+            })
+        })
+        .collect()
+}
+
+fn query_usb_device(
+    sys_path: PathBuf,
+    inputs: &[SysfsInputDevice],
+) -> Result<SysfsUsbDevice, QueryError> {
+    let Some(device_name) = sys_path
+        .file_name()
+        .map(|s| s.to_string_lossy().into_owned())
+    else {
+        return Err(QueryError::Malformed("Malformed USB device path"));
+    };
+
+    // Interfaces are the children named `DEVICE:CONFIG.INTERFACE`, e.g. `1-2:1.0`
+    let mut interfaces = std::fs::read_dir(&sys_path)?
+        .flat_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .strip_prefix(&device_name)
+                .is_some_and(|s| s.starts_with(':'))
+        })
+        .map(|entry| {
+            let interface_path = entry.path().canonicalize()?;
+            Ok(SysfsUsbInterface {
+                number: read_hex_attribute(&interface_path, "bInterfaceNumber")? as u8,
+                driver: read_driver(&interface_path),
+                hid_devices: query_hid_devices(&interface_path, inputs)?,
+                sys_path: interface_path,
+            })
+        })
+        .collect::<Result<Vec<_>, QueryError>>()?;
+    interfaces.sort_by_key(|interface| interface.number);
+
+    Ok(SysfsUsbDevice {
+        id_vendor: read_hex_attribute(&sys_path, "idVendor")?,
+        id_product: read_hex_attribute(&sys_path, "idProduct")?,
+        serial: read_attribute(&sys_path, "serial"),
+        manufacturer: read_attribute(&sys_path, "manufacturer"),
+        product: read_attribute(&sys_path, "product"),
+        interfaces: interfaces.into_boxed_slice(),
+        sys_path,
+    })
+}
+
+/// Query USB devices from `/sys/bus/usb/devices`, optionally only ones from a specific vendor.
+///
+/// Interfaces, HID devices & input devices are grouped under the USB device they physically
+/// belong to.
+pub fn query_usb_devices(id_vendor: Option<u16>) -> Result<Box<[SysfsUsbDevice]>, QueryError> {
+    query_usb_devices_in(Path::new(SYSFS_ROOT), id_vendor)
+}
+
+/// Same as [`query_usb_devices`], with sysfs mounted at `root` instead of `/sys`.
+///
+/// Devices that can't be read are skipped.
+pub fn query_usb_devices_in(
+    root: &Path,
+    id_vendor: Option<u16>,
+) -> Result<Box<[SysfsUsbDevice]>, QueryError> {
+    let inputs = query_input_devices(root)?;
+
+    let mut devices = Vec::new();
+    for entry in std::fs::read_dir(root.join(SYSFS_USB_DEVICES))?.flat_map(|entry| entry.ok()) {
+        // Skip interfaces (`1-2:1.0`) & root hubs (`usb1`)
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        if file_name.contains(':') || file_name.starts_with("usb") {
+            continue;
+        }
+        let device = entry
+            .path()
+            .canonicalize()
+            .map_err(QueryError::from)
+            .and_then(|sys_path| match id_vendor {
+                Some(id_vendor) if read_hex_attribute(&sys_path, "idVendor")? != id_vendor => {
+                    Ok(None)
+                }
+                _ => query_usb_device(sys_path, &inputs).map(Some),
+            });
+        match device {
+            Ok(Some(device)) => devices.push(device),
+            Ok(None) => {}
+            Err(err) => log::debug!("Skipping USB device {:?}: {err}", entry.path()),
+        }
+    }
+    devices.sort_by(|a, b| a.sys_path.cmp(&b.sys_path));
+    Ok(devices.into_boxed_slice())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        os::unix::fs::symlink,
+        path::{Path, PathBuf},
+    };
+
+    use super::{is_hid_device_name, query_usb_devices_in};
+    use crate::bitmap_contains;
+
+    fn write(path: PathBuf, content: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    fn link(target: &Path, link: PathBuf) {
+        std::fs::create_dir_all(link.parent().unwrap()).unwrap();
+        symlink(target, link).unwrap();
+    }
+
+    /// Input device below `parent`, linked from `class/input`.
+    fn input_device(root: &Path, parent: &Path, number: u32, name: &str) {
+        let path = parent.join(format!("input/input{number}"));
+        write(path.join("name"), name);
+        write(path.join("capabilities/ev"), "120013\n");
+        std::fs::create_dir_all(path.join(format!("event{number}"))).unwrap();
+        link(&path, root.join(format!("class/input/input{number}")));
+    }
+
+    /// USB device with an interface for each `(number, hid driver)`, linked from `bus/usb/devices`.
+    ///
+    /// Input devices are numbered from `first_input` + the interface number.
+    fn usb_device(
+        root: &Path,
+        name: &str,
+        ids: (&str, &str),
+        interfaces: &[(u8, &str)],
+        first_input: u32,
+    ) {
+        let path = root.join("devices/pci0000:00/usb1").join(name);
+        write(path.join("idVendor"), ids.0);
+        write(path.join("idProduct"), ids.1);
+        write(path.join("serial"), "PM1234\n");
+        link(&path, root.join("bus/usb/devices").join(name));
+        for (number, driver) in interfaces {
+            let interface = path.join(format!("{name}:1.{number}"));
+            write(
+                interface.join("bInterfaceNumber"),
+                &format!("{number:02x}\n"),
+            );
+            link(
+                &interface,
+                root.join("bus/usb/devices")
+                    .join(format!("{name}:1.{number}")),
+            );
+            let hid = interface.join(format!("0003:{}:{}.000{number}", ids.0, ids.1));
+            std::fs::create_dir_all(&hid).unwrap();
+            link(
+                &root.join("bus/hid/drivers").join(driver),
+                hid.join("driver"),
+            );
+            let number = first_input + *number as u32;
+            input_device(root, &hid, number, &format!("{name} {number}"));
+        }
+    }
+
+    #[test]
+    fn usb_devices() {
+        let root = std::env::temp_dir().join(format!("openrazer-sysfs-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        usb_device(
+            &root,
+            "1-2",
+            ("1532", "021e"),
+            &[(2, "razerkbd"), (0, "razerkbd")],
+            10,
+        );
+        usb_device(&root, "1-3", ("046d", "c52b"), &[(0, "hid-generic")], 20);
+        link(
+            &root.join("devices/pci0000:00/usb1"),
+            root.join("bus/usb/devices/usb1"),
+        );
+        // Input devices that aren't USB, or can't be read, don't stop the others being found.
+        input_device(
+            &root,
+            &root.join("devices/platform/i8042/serio0"),
+            1,
+            "AT keyboard",
+        );
+        link(&root.join("devices/gone"), root.join("class/input/input99"));
+
+        let devices = query_usb_devices_in(&root, Some(0x1532)).unwrap();
+        assert_eq!(devices.len(), 1);
+        let device = &devices[0];
+        assert_eq!((device.id_vendor, device.id_product), (0x1532, 0x021E));
+        assert_eq!(device.serial.as_deref(), Some("PM1234"));
+        assert_eq!(
+            device
+                .interfaces
+                .iter()
+                .map(|interface| interface.number)
+                .collect::<Vec<_>>(),
+            [0, 2]
+        );
+        for interface in device.interfaces.iter() {
+            let [hid] = &interface.hid_devices[..] else {
+                panic!("Expected one HID device on interface {}", interface.number);
+            };
+            assert_eq!(hid.driver.as_deref(), Some("razerkbd"));
+            let [input] = &hid.inputs[..] else {
+                panic!(
+                    "Expected one input device on interface {}",
+                    interface.number
+                );
+            };
+            assert_eq!(input.name, format!("1-2 {}", 10 + interface.number));
+            assert_eq!(
+                input.event_path,
+                Some(PathBuf::from(format!(
+                    "/dev/input/event{}",
+                    10 + interface.number
+                )))
+            );
+            assert!(bitmap_contains(&input.capabilities["EV"], 1));
+        }
+
+        let devices = query_usb_devices_in(&root, None).unwrap();
+        assert_eq!(
+            devices
+                .iter()
+                .map(|device| device.id_vendor)
+                .collect::<Vec<_>>(),
+            [0x1532, 0x046D]
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn hid_device_name() {
+        assert!(is_hid_device_name("0003:1532:021E.0008"));
+        assert!(!is_hid_device_name("1-2:1.2"));
+        assert!(!is_hid_device_name("input32"));
+        assert!(!is_hid_device_name("0003:1532.0008"));
+    }
+}
@@ -1,57 +1,199 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use regex::Regex;
 
+#[cfg(feature = "input")]
+use crate::EvdevDeviceNonblocking;
 use crate::{
-    query_devices, DeviceMatrixEffectManager, EvdevDeviceNonblocking, OpenRazerError, QueryDevice,
-    RAZER_DEVICE_VENDOR_ID,
+    bitmap_contains, query_devices, query_usb_devices, DeviceMatrixEffectManager, OpenRazerError,
+    QueryDevice, SysfsUsbDevice, RAZER_DEVICE_VENDOR_ID,
 };
 
-#[derive(Debug)]
+// https://github.com/torvalds/linux/blob/master/include/uapi/linux/input-event-codes.h
+const EV_KEY: usize = 0x01;
+const EV_LED: usize = 0x11;
+
+#[derive(Debug, Clone)]
+pub struct RazerInput {
+    pub name: String,
+    /// Path of the evdev node, e.g. `/dev/input/event5`
+    pub event_path: Option<PathBuf>,
+    /// Has the `kbd` handler (Reports keys)
+    pub keyboard: bool,
+    /// Has the `leds` handler (Reports lock key LEDs)
+    pub leds: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct RazerInterface {
+    pub number: Option<u8>,
+    /// Directory where the OpenRazer driver exposes its attributes for this interface.
+    pub driver_path: Option<PathBuf>,
+    pub inputs: Box<[RazerInput]>,
+}
+
+#[derive(Debug, Clone)]
 pub struct RazerDevice {
-    query_devices: Box<[QueryDevice]>,
+    /// Sysfs path of the USB device, unique while it's plugged in.
+    pub sys_path: PathBuf,
+    pub id_product: u16,
+    pub serial: Option<String>,
+    pub name: Option<String>,
+    pub interfaces: Box<[RazerInterface]>,
+}
+
+fn is_driver_path(path: &Path) -> bool {
+    path.join("matrix_effect_none").exists()
 }
 
 impl RazerDevice {
-    pub fn get_matrix_manager(&self) -> Result<Option<DeviceMatrixEffectManager>, OpenRazerError> {
-        // Just search all devices if they have the openrazer stuff :)
-        for device in self.query_devices.iter() {
+    fn from_sysfs(device: SysfsUsbDevice) -> Self {
+        Self {
+            sys_path: device.sys_path,
+            id_product: device.id_product,
+            serial: device.serial,
+            name: device.product,
+            interfaces: device
+                .interfaces
+                .into_iter()
+                .map(|interface| RazerInterface {
+                    number: Some(interface.number),
+                    driver_path: interface
+                        .hid_devices
+                        .iter()
+                        .find(|hid| is_driver_path(&hid.sys_path))
+                        .map(|hid| hid.sys_path.clone()),
+                    inputs: interface
+                        .hid_devices
+                        .iter()
+                        .flat_map(|hid| hid.inputs.iter())
+                        .map(|input| {
+                            let ev = input
+                                .capabilities
+                                .get("EV")
+                                .map(|ev| ev.as_ref())
+                                .unwrap_or_default();
+                            RazerInput {
+                                name: input.name.clone(),
+                                event_path: input.event_path.clone(),
+                                keyboard: bitmap_contains(ev, EV_KEY),
+                                leds: bitmap_contains(ev, EV_LED),
+                            }
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+
+    fn from_query_devices(devices: Vec<QueryDevice>) -> Self {
+        // Group by the HID device directory, which is where the driver attributes live.
+        // The sys path looks something like: /devices/pci0000:00/0000:00:02.1/0000:16:00.0/usb1/1-2/1-2:1.2/0003:1532:021E.0008/input/input32
+        let mut interfaces: HashMap<PathBuf, Vec<RazerInput>> = HashMap::new();
+        for device in devices.iter() {
             let mut path = PathBuf::from(format!("/sys/{}", device.sys_path));
             path.pop();
             path.pop();
-            if std::fs::read_dir(&path)?
-                .flat_map(|entry| entry.ok())
-                .any(|entry| entry.path().ends_with("matrix_effect_none"))
-            {
-                return Ok(Some(DeviceMatrixEffectManager::new(path)));
-            }
+            interfaces.entry(path).or_default().push(RazerInput {
+                name: device.name.clone(),
+                event_path: device
+                    .handlers
+                    .iter()
+                    .find(|handler| handler.starts_with("event"))
+                    .map(|event| Path::new("/dev/input/").join(event)),
+                keyboard: device.handlers.iter().any(|handler| handler == "kbd"),
+                leds: device.handlers.iter().any(|handler| handler == "leds"),
+            });
+        }
+
+        let mut interfaces = interfaces
+            .into_iter()
+            .map(|(path, inputs)| RazerInterface {
+                // Parent directory is the USB interface: `1-2:1.2`
+                number: path
+                    .parent()
+                    .and_then(|interface| interface.file_name()?.to_str()?.rsplit_once('.'))
+                    .and_then(|(_, number)| u8::from_str_radix(number, 16).ok()),
+                driver_path: is_driver_path(&path).then_some(path),
+                inputs: inputs.into_boxed_slice(),
+            })
+            .collect::<Vec<_>>();
+        interfaces.sort_by_key(|interface| interface.number);
+
+        // `.../1-2/1-2:1.2/0003:1532:021E.0008/input/input32` -> `.../1-2`
+        let mut sys_path = PathBuf::from(format!("/sys/{}", devices[0].sys_path));
+        (0..4).for_each(|_| {
+            sys_path.pop();
+        });
+
+        Self {
+            sys_path,
+            id_product: devices[0].id_product,
+            serial: None,
+            name: Some(devices[0].name.clone()),
+            interfaces: interfaces.into_boxed_slice(),
         }
-        Ok(None)
     }
 
+    pub fn inputs(&self) -> impl Iterator<Item = &RazerInput> {
+        self.interfaces
+            .iter()
+            .flat_map(|interface| interface.inputs.iter())
+    }
+
+    pub fn get_matrix_manager(&self) -> Result<Option<DeviceMatrixEffectManager>, OpenRazerError> {
+        Ok(self
+            .interfaces
+            .iter()
+            .find_map(|interface| interface.driver_path.clone())
+            .map(DeviceMatrixEffectManager::new))
+    }
+
+    #[cfg(feature = "input")]
     pub fn get_evdev_device(&self) -> Result<Option<EvdevDeviceNonblocking>, OpenRazerError> {
         // FIXME: This is definitely not a good way to get what one is actually keyboard inputs.
-        for device in self.query_devices.iter() {
-            if device.handlers.contains(&"kbd".to_owned())
-                && !device.handlers.contains(&"leds".to_owned())
-            {
-                if let Some(event) = device
-                    .handlers
-                    .iter()
-                    .find(|handler| handler.starts_with("event"))
-                {
-                    let mut path = PathBuf::from("/dev/input/");
-                    path.push(event);
-                    log::info!("Reading keyboard events from {path:?}");
-                    return Ok(Some(EvdevDeviceNonblocking::new(&path)?));
-                }
-            }
-        }
-        Ok(None)
+        let Some(path) = self
+            .inputs()
+            .filter(|input| input.keyboard && !input.leds)
+            .find_map(|input| input.event_path.as_ref())
+        else {
+            return Ok(None);
+        };
+        log::info!("Reading keyboard events from {path:?}");
+        Ok(Some(EvdevDeviceNonblocking::new(path)?))
+    }
+
+    /// The input that has the lock key LEDs, for reading Caps/Num/Scroll Lock state.
+    #[cfg(feature = "input")]
+    pub fn get_led_evdev_device(&self) -> Result<Option<EvdevDeviceNonblocking>, OpenRazerError> {
+        let Some(path) = self
+            .inputs()
+            .filter(|input| input.leds)
+            .find_map(|input| input.event_path.as_ref())
+        else {
+            return Ok(None);
+        };
+        log::info!("Reading LED events from {path:?}");
+        Ok(Some(EvdevDeviceNonblocking::new(path)?))
     }
 }
 
-pub fn query_razer_devices() -> Result<Box<[RazerDevice]>, OpenRazerError> {
+/// Query Razer devices by walking `/sys/bus/usb/devices` & `/sys/class/input`.
+pub fn query_razer_devices_sysfs() -> Result<Box<[RazerDevice]>, OpenRazerError> {
+    Ok(query_usb_devices(Some(RAZER_DEVICE_VENDOR_ID))?
+        .into_iter()
+        .map(RazerDevice::from_sysfs)
+        .collect())
+}
+
+/// Query Razer devices by parsing `/proc/bus/input/devices`.
+///
+/// This only sees devices that have inputs, and has to guess which inputs belong to the same
+/// physical device.
+pub fn query_razer_devices_proc() -> Result<Box<[RazerDevice]>, OpenRazerError> {
     let mut groups: HashMap<String, Vec<QueryDevice>> = HashMap::new();
 
     query_devices()?
@@ -77,8 +219,16 @@ pub fn query_razer_devices() -> Result<Box<[RazerDevice]>, OpenRazerError> {
 
     Ok(groups
         .into_values()
-        .map(|devices| RazerDevice {
-            query_devices: devices.into_boxed_slice(),
-        })
+        .map(RazerDevice::from_query_devices)
         .collect())
 }
+
+/// Query Razer devices, using sysfs & falling back to `/proc/bus/input/devices`.
+pub fn query_razer_devices() -> Result<Box<[RazerDevice]>, OpenRazerError> {
+    match query_razer_devices_sysfs() {
+        Ok(devices) if !devices.is_empty() => return Ok(devices),
+        Ok(_) => log::debug!("No Razer devices found in sysfs, falling back to /proc"),
+        Err(err) => log::warn!("Failed to query sysfs, falling back to /proc: {err}"),
+    }
+    query_razer_devices_proc()
+}
@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{KeyCode, MATRIX_HEIGHT, MATRIX_WIDTH};
+
+/// Maps keys to matrix cells & back.
+///
+/// Keys can be on multiple cells (e.g. the space bar), and cells can have multiple keys (e.g. F1
+/// & mute on boards that emulate media keys with fn).
+///
+/// Cells also have a physical position for effects to measure distances with, in key units (one
+/// standard key, 19.05mm). Cells without a position are placed on the grid.
+///
+/// Keys can be put in named groups (e.g. `"wasd"`) so effects & the config can target them.
+#[derive(Debug, Default, Clone)]
+pub struct MatrixMapper {
+    mapping: HashMap<KeyCode, Vec<(usize, usize)>>,
+    reverse: BTreeMap<(usize, usize), Vec<KeyCode>>,
+    positions: HashMap<(usize, usize), (f32, f32)>,
+    groups: BTreeMap<String, Vec<KeyCode>>,
+}
+
+impl MatrixMapper {
+    /// Add a cell to a key.
+    pub fn add_mapping(&mut self, key: KeyCode, x: usize, y: usize) {
+        if x >= MATRIX_WIDTH || y >= MATRIX_HEIGHT {
+            log::warn!("Cannot add mapping is outside of matrix: {key:?} at {x},{y}");
+            return;
+        }
+        let cells = self.mapping.entry(key).or_default();
+        if cells.contains(&(x, y)) {
+            return;
+        }
+        cells.push((x, y));
+        self.reverse.entry((x, y)).or_default().push(key);
+    }
+
+    pub fn add_mappings<T>(&mut self, iter: T)
+    where
+        T: IntoIterator<Item = (KeyCode, (usize, usize))>,
+    {
+        iter.into_iter()
+            .for_each(|(key, (x, y))| self.add_mapping(key, x, y));
+    }
+
+    /// Remove a key & all of its cells.
+    pub fn remove_mapping(&mut self, key: KeyCode) {
+        let Some(cells) = self.mapping.remove(&key) else {
+            return;
+        };
+        for cell in cells {
+            if let Some(keys) = self.reverse.get_mut(&cell) {
+                keys.retain(|k| *k != key);
+                if keys.is_empty() {
+                    self.reverse.remove(&cell);
+                }
+            }
+        }
+    }
+
+    /// First cell of a key.
+    pub fn map(&self, key: KeyCode) -> Option<(usize, usize)> {
+        self.cells(key).first().cloned()
+    }
+
+    /// All cells of a key.
+    pub fn cells(&self, key: KeyCode) -> &[(usize, usize)] {
+        self.mapping
+            .get(&key)
+            .map(|cells| cells.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// All keys on a cell.
+    pub fn keys_at(&self, x: usize, y: usize) -> &[KeyCode] {
+        self.reverse
+            .get(&(x, y))
+            .map(|keys| keys.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn is_populated(&self, x: usize, y: usize) -> bool {
+        self.reverse.contains_key(&(x, y))
+    }
+
+    /// Cells that have a key, in row order.
+    pub fn populated(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let mut cells = self.reverse.keys().cloned().collect::<Vec<_>>();
+        cells.sort_by_key(|(x, y)| (*y, *x));
+        cells.into_iter()
+    }
+
+    pub fn set_position(&mut self, x: usize, y: usize, pos: (f32, f32)) {
+        self.positions.insert((x, y), pos);
+    }
+
+    /// Physical position of a cell.
+    pub fn position(&self, x: usize, y: usize) -> (f32, f32) {
+        self.positions
+            .get(&(x, y))
+            .cloned()
+            .unwrap_or((x as f32, y as f32))
+    }
+
+    /// Middle of some cells (e.g. all cells of a key), [`None`] if there are none.
+    pub fn center(&self, cells: &[(usize, usize)]) -> Option<(f32, f32)> {
+        if cells.is_empty() {
+            return None;
+        }
+        let (x, y) = cells
+            .iter()
+            .map(|(x, y)| self.position(*x, *y))
+            .fold((0.0, 0.0), |(ax, ay), (x, y)| (ax + x, ay + y));
+        Some((x / cells.len() as f32, y / cells.len() as f32))
+    }
+
+    /// Top left & bottom right of the populated cells, or of the whole matrix if there are none.
+    pub fn bounds(&self) -> ((f32, f32), (f32, f32)) {
+        let mut positions = self.populated().map(|(x, y)| self.position(x, y));
+        let Some(first) = positions.next() else {
+            return (
+                (0.0, 0.0),
+                ((MATRIX_WIDTH - 1) as f32, (MATRIX_HEIGHT - 1) as f32),
+            );
+        };
+        positions.fold((first, first), |(min, max), (x, y)| {
+            ((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y)))
+        })
+    }
+
+    /// Add or replace a group.
+    pub fn set_group<T>(&mut self, name: &str, keys: T)
+    where
+        T: IntoIterator<Item = KeyCode>,
+    {
+        self.groups
+            .insert(name.to_owned(), keys.into_iter().collect());
+    }
+
+    pub fn group(&self, name: &str) -> Option<&[KeyCode]> {
+        self.groups.get(name).map(|keys| keys.as_slice())
+    }
+
+    /// Cells of all keys in a group, in row order.
+    pub fn group_cells(&self, name: &str) -> Option<Vec<(usize, usize)>> {
+        let mut cells = self
+            .group(name)?
+            .iter()
+            .flat_map(|key| self.cells(*key))
+            .cloned()
+            .collect::<Vec<_>>();
+        cells.sort_by_key(|(x, y)| (*y, *x));
+        cells.dedup();
+        Some(cells)
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = (&str, &[KeyCode])> {
+        self.groups
+            .iter()
+            .map(|(name, keys)| (name.as_str(), keys.as_slice()))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = (KeyCode, &[(usize, usize)])> {
+        self.mapping
+            .iter()
+            .map(|(key, cells)| (*key, cells.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MatrixMapper;
+    use crate::{KeyCode, MATRIX_WIDTH};
+
+    #[test]
+    fn mapping() {
+        let mut mapper = MatrixMapper::default();
+        mapper.add_mappings([
+            (KeyCode::KEY_A, (2, 3)),
+            (KeyCode::KEY_B, (MATRIX_WIDTH, 0)),
+            (KeyCode::KEY_SPACE, (6, 5)),
+            (KeyCode::KEY_SPACE, (7, 5)),
+            (KeyCode::KEY_F1, (3, 0)),
+            (KeyCode::KEY_MUTE, (3, 0)),
+        ]);
+        assert_eq!(mapper.map(KeyCode::KEY_A), Some((2, 3)));
+        assert_eq!(mapper.map(KeyCode::KEY_B), None);
+        assert_eq!(mapper.cells(KeyCode::KEY_SPACE), &[(6, 5), (7, 5)]);
+        assert_eq!(mapper.keys_at(3, 0), &[KeyCode::KEY_F1, KeyCode::KEY_MUTE]);
+        assert!(!mapper.is_populated(0, 0));
+        assert_eq!(
+            mapper.populated().collect::<Vec<_>>(),
+            [(3, 0), (2, 3), (6, 5), (7, 5)]
+        );
+
+        mapper.remove_mapping(KeyCode::KEY_F1);
+        assert_eq!(mapper.keys_at(3, 0), &[KeyCode::KEY_MUTE]);
+
+        mapper.set_position(7, 5, (8.0, 5.5));
+        assert_eq!(mapper.position(6, 5), (6.0, 5.0));
+        assert_eq!(
+            mapper.center(mapper.cells(KeyCode::KEY_SPACE)),
+            Some((7.0, 5.25))
+        );
+        assert_eq!(mapper.bounds(), ((2.0, 0.0), (8.0, 5.5)));
+
+        mapper.set_group("test", [KeyCode::KEY_SPACE, KeyCode::KEY_A, KeyCode::KEY_B]);
+        assert_eq!(
+            mapper.group_cells("test"),
+            Some(vec![(2, 3), (6, 5), (7, 5)])
+        );
+        assert_eq!(mapper.group_cells("missing"), None);
+    }
+}
@@ -1,6 +1,6 @@
-use std::{collections::HashMap, fs::File, io::Write, path::PathBuf};
+use std::{fs::File, io::Write, path::PathBuf};
 
-use crate::{Color, OpenRazerError};
+use crate::{Canvas, Color, OpenRazerError};
 
 #[derive(Debug)]
 pub struct DeviceMatrixEffectManager {
@@ -76,7 +76,6 @@ impl DeviceMatrixEffectManager {
 
 pub const MATRIX_WIDTH: usize = 22;
 pub const MATRIX_HEIGHT: usize = 6;
-const MATRIX_SIZE: usize = MATRIX_WIDTH * MATRIX_HEIGHT;
 
 #[derive(Debug)]
 pub struct DeviceMatrixCustom<'a> {
@@ -86,7 +85,7 @@ pub struct DeviceMatrixCustom<'a> {
     file_matrix: File,
     file_update: File,
 
-    matrix: [Color; MATRIX_SIZE],
+    canvas: Canvas,
 }
 
 impl<'a> DeviceMatrixCustom<'a> {
@@ -99,43 +98,48 @@ impl<'a> DeviceMatrixCustom<'a> {
                 .append(true)
                 .open(matrix_manager.subpath("matrix_effect_custom"))?,
             matrix_manager,
-            matrix: [Color::new(0.0, 0.0, 0.0); MATRIX_SIZE],
+            canvas: Canvas::new(MATRIX_WIDTH, MATRIX_HEIGHT),
         })
     }
 }
 
 impl DeviceMatrixCustom<'_> {
-    fn index(&self, x: usize, y: usize) -> Option<usize> {
-        if x >= MATRIX_WIDTH || y >= MATRIX_HEIGHT {
-            return None;
-        }
-        Some(x + y * MATRIX_WIDTH)
-    }
-
     pub fn get(&self, x: usize, y: usize) -> Option<&Color> {
-        self.matrix.get(self.index(x, y)?)
+        self.canvas.get(x, y)
     }
 
     pub fn get_mut(&mut self, x: usize, y: usize) -> Option<&mut Color> {
-        let index = self.index(x, y)?;
-        self.matrix.get_mut(index)
+        self.canvas.get_mut(x, y)
     }
 
     pub fn iter(&self) -> impl Iterator<Item = (usize, usize, &Color)> {
-        self.matrix
-            .iter()
-            .enumerate()
-            .map(|(i, c)| (i % MATRIX_WIDTH, i / MATRIX_WIDTH, c))
+        self.canvas.iter()
     }
 
     pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, usize, &mut Color)> {
-        self.matrix
-            .iter_mut()
-            .enumerate()
-            .map(|(i, c)| (i % MATRIX_WIDTH, i / MATRIX_WIDTH, c))
+        self.canvas.iter_mut()
     }
 
-    pub fn send_update(&mut self) -> Result<(), OpenRazerError> {
+    pub fn canvas(&self) -> &Canvas {
+        &self.canvas
+    }
+
+    /// Copy a canvas onto the matrix, cells outside of the matrix are ignored.
+    pub fn set_canvas(&mut self, canvas: &Canvas) {
+        for (x, y, color) in self.canvas.iter_mut() {
+            if let Some(new) = canvas.get(x, y) {
+                *color = *new;
+            }
+        }
+    }
+
+    /// Same as [`DeviceMatrixCustom::set_canvas`] & [`DeviceMatrixCustom::send_update`].
+    pub fn send_canvas(&mut self, canvas: &Canvas) -> Result<(), OpenRazerError> {
+        self.set_canvas(canvas);
+        self.send_update()
+    }
+
+    fn frame_data(&self) -> Result<Vec<u8>, OpenRazerError> {
         // There are 2 streams:
         // - 'matrix_effect_custom': Update matrix with new frame data.
         // - 'matrix_custom_frame': Frame data for a packet.
@@ -157,45 +161,42 @@ impl DeviceMatrixCustom<'_> {
         for y in 0..MATRIX_HEIGHT {
             data.write_all(&[y as u8, 0, MATRIX_WIDTH as u8 - 1])?;
             for x in 0..MATRIX_WIDTH {
-                let color = self.matrix[x + y * MATRIX_WIDTH];
+                let color = self.canvas.get(x, y).cloned().unwrap_or_default();
                 data.write_all(&color.to_quantized())?;
             }
         }
 
-        if !data.is_empty() {
-            self.file_matrix.write_all(&data)?;
-            self.file_matrix.flush()?;
-            self.file_update.write_all(&[1])?;
-            self.file_update.flush()?;
-        }
-
-        Ok(())
+        Ok(data)
     }
-}
 
-#[derive(Debug, Default)]
-pub struct MatrixMapper {
-    mapping: HashMap<evdev::KeyCode, (usize, usize)>,
-}
-
-impl MatrixMapper {
-    pub fn add_mapping(&mut self, key: evdev::KeyCode, x: usize, y: usize) {
-        if x >= MATRIX_WIDTH || y >= MATRIX_HEIGHT {
-            log::warn!("Cannot add mapping is outside of matrix: {key:?} at {x},{y}");
-            return;
-        }
-        self.mapping.insert(key, (x, y));
+    pub fn send_update(&mut self) -> Result<(), OpenRazerError> {
+        let data = self.frame_data()?;
+        write_frame(&mut self.file_matrix, &mut self.file_update, &data)
     }
 
-    pub fn add_mappings<T>(&mut self, iter: T)
-    where
-        T: IntoIterator<Item = (evdev::KeyCode, (usize, usize))>,
-    {
-        iter.into_iter()
-            .for_each(|(key, (x, y))| self.add_mapping(key, x, y));
+    /// Same as [`DeviceMatrixCustom::send_update`], but the write happens on tokio's blocking
+    /// thread pool.
+    #[cfg(feature = "tokio")]
+    pub async fn send_update_async(&mut self) -> Result<(), OpenRazerError> {
+        let data = self.frame_data()?;
+        let mut file_matrix = self.file_matrix.try_clone()?;
+        let mut file_update = self.file_update.try_clone()?;
+        tokio::task::spawn_blocking(move || write_frame(&mut file_matrix, &mut file_update, &data))
+            .await
+            .map_err(std::io::Error::other)?
     }
+}
 
-    pub fn map(&self, key: evdev::KeyCode) -> Option<(usize, usize)> {
-        self.mapping.get(&key).cloned()
-    }
+fn write_frame(
+    file_matrix: &mut File,
+    file_update: &mut File,
+    data: &[u8],
+) -> Result<(), OpenRazerError> {
+    if !data.is_empty() {
+        file_matrix.write_all(data)?;
+        file_matrix.flush()?;
+        file_update.write_all(&[1])?;
+        file_update.flush()?;
+    }
+    Ok(())
 }
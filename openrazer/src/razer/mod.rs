@@ -2,9 +2,13 @@
 use thiserror::Error;
 
 mod device;
+#[cfg(feature = "input")]
+mod mapper;
 mod matrix;
 
 pub use device::*;
+#[cfg(feature = "input")]
+pub use mapper::*;
 pub use matrix::*;
 
 use crate::QueryError;